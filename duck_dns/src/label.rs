@@ -33,7 +33,7 @@ impl From<idna::Errors> for ParseLabelError {
 
 impl StdError for ParseLabelError {}
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Label(pub(crate) String);
 
 impl fmt::Display for Label {
@@ -2,19 +2,72 @@ mod label;
 mod options;
 mod responses;
 
-use std::{error::Error as StdError, fmt, str::FromStr};
+use std::{error::Error as StdError, fmt, net::IpAddr, str::FromStr, sync::Arc};
 
 use log::debug;
-use url::Url;
+use reqwest::StatusCode;
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer,
+};
+use url::{form_urlencoded, Url, UrlQuery};
+use zeroize::Zeroize;
 
 pub use crate::{label::*, options::*, responses::*};
 
+/// Mask the value of every `token` query parameter in `s`.
+///
+/// This works without knowing the token, so can be applied to anything that
+/// might include a request URL, like log messages from other crates.
+pub fn scrub_tokens(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find("token=") {
+        let (before, after) = rest.split_at(i + "token=".len());
+        out.push_str(before);
+        let end = after
+            .find(|c: char| matches!(c, '&' | '#' | '"' | '\'' | ')' | '>') || c.is_whitespace())
+            .unwrap_or(after.len());
+        out.extend(after[..end].chars().map(|_| '*'));
+        rest = &after[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// A DuckDNS account token, zeroed when dropped.
+///
+/// It can't be cloned, so there's only ever one copy of it to zero. Share it
+/// with an `Arc` instead.
 pub struct Token(String);
 
 impl Token {
+    // mask this token anywhere in `s`, along with any `token` parameter
     fn scrub(&self, s: &str) -> String {
+        let s = scrub_tokens(s);
+        // a token so short it's part of the parameter name has been masked
+        // already, and replacing it would mangle the name
+        if self.0.is_empty() || "token=".contains(self.0.as_str()) {
+            return s;
+        }
         s.replace(&self.0, &"*".repeat(self.0.len()))
     }
+
+    // reqwest includes the URL in its errors, so that's masked too
+    fn scrub_error(&self, e: reqwest::Error) -> Error {
+        let url = e.url().map(|url| Url::parse(&self.scrub(url.as_str())));
+        Error::Http(match url {
+            Some(Ok(url)) => e.with_url(url),
+            Some(Err(_)) => e.without_url(),
+            None => e,
+        })
+    }
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
 }
 
 impl fmt::Debug for Token {
@@ -25,6 +78,41 @@ impl fmt::Debug for Token {
     }
 }
 
+// deserialized straight into a token, rather than through a string that
+// would be left behind unzeroed
+impl<'de> Deserialize<'de> for Token {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_string(TokenVisitor)
+    }
+}
+
+struct TokenVisitor;
+
+impl<'de> Visitor<'de> for TokenVisitor {
+    type Value = Token;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a token")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Token, E>
+    where
+        E: de::Error,
+    {
+        Ok(Token::from(v))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Token, E>
+    where
+        E: de::Error,
+    {
+        Ok(Token::from(v))
+    }
+}
+
 impl From<String> for Token {
     fn from(val: String) -> Self {
         Token(val)
@@ -40,7 +128,7 @@ impl From<&str> for Token {
 #[derive(Debug)]
 pub enum Error {
     Http(reqwest::Error),
-    HttpBadResponse(reqwest::Response),
+    HttpBadResponse(StatusCode),
     ParseResponse(ParseResponseError),
     ParseTxtResponse(ParseTxtResponseError),
 }
@@ -49,7 +137,7 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Http(e) => e.fmt(f),
-            Error::HttpBadResponse(res) => write!(f, "bad response: {}", res.status()),
+            Error::HttpBadResponse(status) => write!(f, "bad response: {}", status),
             Error::ParseResponse(e) => e.fmt(f),
             Error::ParseTxtResponse(e) => e.fmt(f),
         }
@@ -76,10 +164,12 @@ impl From<ParseTxtResponseError> for Error {
     }
 }
 
+#[derive(Clone)]
 pub struct Client {
     url: Url,
     domains: Vec<Label>,
-    token: Token,
+    token: Arc<Token>,
+    local_address: Option<IpAddr>,
 }
 
 impl Client {
@@ -87,30 +177,107 @@ impl Client {
     where
         T: Into<Token>,
     {
-        let url = Url::parse("https://www.duckdns.org/update")
-            .expect("hardcoded URL shouldn't fail to parse");
-        Self::with_base(url, domains, token)
+        Self::from_shared(domains, Arc::new(token.into()))
     }
 
     pub fn with_base<T>(url: Url, domains: Vec<Label>, token: T) -> Self
     where
         T: Into<Token>,
     {
+        Self::with_base_shared(url, domains, Arc::new(token.into()))
+    }
+
+    /// Create a client using a token shared with other clients, rather than
+    /// a copy of it.
+    pub fn from_shared(domains: Vec<Label>, token: Arc<Token>) -> Self {
+        let url = Url::parse("https://www.duckdns.org/update")
+            .expect("hardcoded URL shouldn't fail to parse");
+        Self::with_base_shared(url, domains, token)
+    }
+
+    pub fn with_base_shared(url: Url, domains: Vec<Label>, token: Arc<Token>) -> Self {
         Self {
             url,
             domains,
-            token: token.into(),
+            token,
+            local_address: None,
         }
     }
 
+    /// Make requests from the given local address.
+    ///
+    /// When no IP is given DuckDNS uses the address the request came from,
+    /// so an unspecified address (`0.0.0.0` or `::`) picks whether that's
+    /// the IPv4 or IPv6 address.
+    pub fn local_address(mut self, addr: IpAddr) -> Self {
+        self.local_address = Some(addr);
+        self
+    }
+
     pub async fn update<T>(&self, options: T) -> Result<Response, Error>
     where
         T: Into<UpdateOptions>,
     {
-        let options = options.into();
-        let mut url = self.url.clone();
-        {
-            let mut query = url.query_pairs_mut();
+        self.request(self.update_url(options.into())).await
+    }
+
+    pub async fn clear<T>(&self, options: T) -> Result<Response, Error>
+    where
+        T: Into<ClearOptions>,
+    {
+        self.request(self.clear_url(options.into())).await
+    }
+
+    pub async fn update_txt<T>(&self, options: T) -> Result<TxtResponse, Error>
+    where
+        T: Into<TxtOptions>,
+    {
+        self.request(self.update_txt_url(options.into())).await
+    }
+
+    pub async fn clear_txt<T>(&self, options: T) -> Result<TxtResponse, Error>
+    where
+        T: Into<ClearTxtOptions>,
+    {
+        self.request(self.clear_txt_url(options.into())).await
+    }
+
+    /// Describe the request `update` would make, without making it.
+    ///
+    /// This is the URL, with the token masked.
+    pub fn dry_run_update<T>(&self, options: T) -> String
+    where
+        T: Into<UpdateOptions>,
+    {
+        self.describe(self.update_url(options.into()))
+    }
+
+    /// Describe the request `clear` would make, without making it.
+    pub fn dry_run_clear<T>(&self, options: T) -> String
+    where
+        T: Into<ClearOptions>,
+    {
+        self.describe(self.clear_url(options.into()))
+    }
+
+    /// Describe the request `update_txt` would make, without making it.
+    pub fn dry_run_update_txt<T>(&self, options: T) -> String
+    where
+        T: Into<TxtOptions>,
+    {
+        self.describe(self.update_txt_url(options.into()))
+    }
+
+    /// Describe the request `clear_txt` would make, without making it.
+    pub fn dry_run_clear_txt<T>(&self, options: T) -> String
+    where
+        T: Into<ClearTxtOptions>,
+    {
+        self.describe(self.clear_txt_url(options.into()))
+    }
+
+    fn update_url(&self, options: UpdateOptions) -> Url {
+        self.url(|query| {
             if let Some(ipv4) = options.ipv4 {
                 query.append_pair("ip", &ipv4.to_string());
             }
@@ -120,66 +287,47 @@ impl Client {
             if options.verbose {
                 query.append_pair("verbose", "true");
             }
-        }
-        self.request(url).await
+        })
     }
 
-    pub async fn clear<T>(&self, options: T) -> Result<Response, Error>
-    where
-        T: Into<ClearOptions>,
-    {
-        let options = options.into();
-        let mut url = self.url.clone();
-        {
-            let mut query = url.query_pairs_mut();
+    fn clear_url(&self, options: ClearOptions) -> Url {
+        self.url(|query| {
             query.append_pair("clear", "true");
             if options.verbose {
                 query.append_pair("verbose", "true");
             }
-        }
-        self.request(url).await
+        })
     }
 
-    pub async fn update_txt<T>(&self, options: T) -> Result<TxtResponse, Error>
-    where
-        T: Into<TxtOptions>,
-    {
-        let options = options.into();
-        let mut url = self.url.clone();
-        {
-            let mut query = url.query_pairs_mut();
+    fn update_txt_url(&self, options: TxtOptions) -> Url {
+        self.url(|query| {
             query.append_pair("txt", &options.txt);
             if options.verbose {
                 query.append_pair("verbose", "true");
             }
-        }
-        self.request(url).await
+        })
     }
 
-    pub async fn clear_txt<T>(&self, options: T) -> Result<TxtResponse, Error>
-    where
-        T: Into<ClearTxtOptions>,
-    {
-        let options = options.into();
-        let mut url = self.url.clone();
-        {
-            let mut query = url.query_pairs_mut();
+    fn clear_txt_url(&self, options: ClearTxtOptions) -> Url {
+        self.url(|query| {
             query.append_pair("txt", "");
             query.append_pair("clear", "true");
             if options.verbose {
                 query.append_pair("verbose", "true");
             }
-        }
-        self.request(url).await
+        })
     }
 
-    async fn request<T>(&self, mut url: Url) -> Result<T, Error>
+    // the request URL, with the query parameters added by `f` followed by
+    // the domains and token
+    fn url<F>(&self, f: F) -> Url
     where
-        T: FromStr,
-        Error: From<<T as FromStr>::Err>,
+        F: FnOnce(&mut form_urlencoded::Serializer<UrlQuery>),
     {
+        let mut url = self.url.clone();
         {
             let mut query = url.query_pairs_mut();
+            f(&mut query);
             query.append_pair(
                 "domains",
                 &self
@@ -191,13 +339,117 @@ impl Client {
             );
             query.append_pair("token", &self.token.0);
         }
+        url
+    }
+
+    fn describe(&self, url: Url) -> String {
+        self.token.scrub(url.as_str())
+    }
+
+    async fn request<T>(&self, url: Url) -> Result<T, Error>
+    where
+        T: FromStr,
+        Error: From<<T as FromStr>::Err>,
+    {
+        let mut builder = reqwest::Client::builder();
+        if let Some(addr) = self.local_address {
+            debug!("requesting from {}", addr);
+            builder = builder.local_address(addr);
+        }
         debug!("requesting {}", self.token.scrub(url.as_str()));
-        let res = reqwest::get(url).await?;
+        let res = builder
+            .build()?
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| self.token.scrub_error(e))?;
         debug!("got {} response", res.status());
         if res.status().is_success() {
-            Ok(res.text().await?.parse()?)
+            let text = res.text().await.map_err(|e| self.token.scrub_error(e))?;
+            Ok(text.parse()?)
         } else {
-            Err(Error::HttpBadResponse(res))
+            Err(Error::HttpBadResponse(res.status()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::Arc};
+
+    use super::{scrub_tokens, Client, Error, Token};
+
+    const TOKEN: &str = "0f3a2b4c-5d6e-4f70-8192-a3b4c5d6e7f8";
+
+    #[test]
+    fn it_scrubs_token_parameters() {
+        assert_eq!(
+            scrub_tokens("GET /update?domains=home&token=abc&verbose=true"),
+            "GET /update?domains=home&token=***&verbose=true"
+        );
+        assert_eq!(
+            scrub_tokens("error for url (https://x/?token=abc) and \"token=de\""),
+            "error for url (https://x/?token=***) and \"token=**\""
+        );
+        assert_eq!(scrub_tokens("no token here"), "no token here");
+    }
+
+    #[test]
+    fn it_scrubs_the_token_anywhere() {
+        let token = Token::from(TOKEN);
+        let scrubbed = token.scrub(&format!("{} token={}", TOKEN, TOKEN));
+        assert_eq!(
+            scrubbed,
+            format!("{} token={}", "*".repeat(36), "*".repeat(36))
+        );
+        // too short to replace without mangling the parameter name
+        assert_eq!(Token::from("to").scrub("token=to"), "token=**");
+    }
+
+    #[tokio::test]
+    async fn it_scrubs_the_token_from_errors() {
+        // nothing listening, so the request fails with the URL in the error
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/update?token={}",
+            closed.local_addr().unwrap(),
+            TOKEN
+        );
+        drop(closed);
+        let e = reqwest::get(&url).await.unwrap_err();
+        assert!(e.to_string().contains(TOKEN));
+
+        let e = Token::from(TOKEN).scrub_error(e);
+        assert!(matches!(e, Error::Http(_)));
+        assert!(e.to_string().contains("token=****"), "{}", e);
+        assert!(!e.to_string().contains(TOKEN), "{}", e);
+        assert!(!format!("{:?}", e).contains(TOKEN), "{:?}", e);
+    }
+
+    #[test]
+    fn it_deserializes_without_showing_the_token() {
+        #[derive(serde::Deserialize)]
+        struct Account {
+            token: Token,
+        }
+        let account: Account =
+            serde_json::from_str(&format!("{{\"token\":\"{}\"}}", TOKEN)).unwrap();
+        assert_eq!(account.token.0, TOKEN);
+        assert!(!format!("{:?}", account.token).contains(TOKEN));
+    }
+
+    #[test]
+    fn it_takes_owned_or_shared_tokens() {
+        let domains = vec!["home".parse().unwrap()];
+        let shared = Arc::new(Token::from(TOKEN));
+        let clients = [
+            Client::new(domains.clone(), TOKEN),
+            Client::new(domains.clone(), String::from(TOKEN)),
+            Client::from_shared(domains, shared.clone()),
+        ];
+        for client in &clients {
+            assert_eq!(client.token.0, TOKEN);
         }
+        assert!(Arc::ptr_eq(&clients[2].token, &shared));
     }
 }
@@ -5,7 +5,10 @@ use std::{
     str::FromStr,
 };
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+use serde::Serialize;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum Status {
     Ok,
     Ko,
@@ -20,7 +23,8 @@ impl fmt::Display for Status {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum Updated {
     Updated,
     Nochange,
@@ -62,7 +66,7 @@ impl From<AddrParseError> for ParseResponseError {
 
 impl StdError for ParseResponseError {}
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Response {
     status: Status,
     ipv4: Option<Ipv4Addr>,
@@ -134,7 +138,7 @@ impl FromStr for Response {
             None => None,
         };
         let last = lines.next();
-        if !(last == None || (last == Some("") && lines.next() == None)) {
+        if !(last.is_none() || (last == Some("") && lines.next().is_none())) {
             return Err(ParseResponseError::ExpectedEnd);
         }
         Ok(Response {
@@ -165,7 +169,7 @@ impl fmt::Display for ParseTxtResponseError {
 
 impl StdError for ParseTxtResponseError {}
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct TxtResponse {
     status: Status,
     txt: Option<String>,
@@ -211,10 +215,7 @@ impl FromStr for TxtResponse {
             Some("KO") => Status::Ko,
             _ => return Err(ParseTxtResponseError::UnrecognizedStatus),
         };
-        let txt = match lines.next() {
-            Some(txt) => Some(txt.to_owned()),
-            None => None,
-        };
+        let txt = lines.next().map(|txt| txt.to_owned());
         let updated = match lines.next() {
             Some("UPDATED") => Some(Updated::Updated),
             Some("NOCHANGE") => Some(Updated::Nochange),
@@ -222,7 +223,7 @@ impl FromStr for TxtResponse {
             None => None,
         };
         let last = lines.next();
-        if !(last == None || (last == Some("") && lines.next() == None)) {
+        if !(last.is_none() || (last == Some("") && lines.next().is_none())) {
             return Err(ParseTxtResponseError::ExpectedEnd);
         }
         Ok(TxtResponse {
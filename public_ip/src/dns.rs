@@ -0,0 +1,180 @@
+use std::{
+    error::Error as StdError,
+    fmt, io,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+use log::{debug, trace};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    time::timeout,
+};
+use trust_dns_client::{
+    op::{Message, MessageType, OpCode, Query},
+    rr::{DNSClass, Name, RData, RecordType},
+};
+
+use crate::{Error, Family, Transport};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct ParseDnsServerError();
+
+impl fmt::Display for ParseDnsServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DNS server must be a host name, IP address, or IP:port")
+    }
+}
+
+impl StdError for ParseDnsServerError {}
+
+/// DNS server to query.
+///
+/// Host names are resolved afresh for every query, so a long running process
+/// follows the server if its address changes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DnsServer {
+    Host(Name),
+    Ip(IpAddr),
+    SocketAddr(SocketAddr),
+}
+
+impl DnsServer {
+    pub(crate) async fn socket_addr(&self, family: Option<Family>) -> Result<SocketAddr, Error> {
+        match self {
+            DnsServer::Host(name) => {
+                debug!("resolving {}", name);
+                lookup_host((name.to_string().as_ref(), 53))
+                    .await?
+                    .find(|addr| family.is_none() || family == Some(Family::of(&addr.ip())))
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, "could not resolve host").into()
+                    })
+            }
+            DnsServer::Ip(ip) => Ok((*ip, 53).into()),
+            DnsServer::SocketAddr(addr) => Ok(*addr),
+        }
+    }
+}
+
+impl From<Name> for DnsServer {
+    fn from(val: Name) -> Self {
+        DnsServer::Host(val)
+    }
+}
+
+impl From<IpAddr> for DnsServer {
+    fn from(val: IpAddr) -> Self {
+        DnsServer::Ip(val)
+    }
+}
+
+impl From<SocketAddr> for DnsServer {
+    fn from(val: SocketAddr) -> Self {
+        DnsServer::SocketAddr(val)
+    }
+}
+
+impl fmt::Display for DnsServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsServer::Host(name) => name.fmt(f),
+            DnsServer::Ip(ip) => ip.fmt(f),
+            DnsServer::SocketAddr(addr) => addr.fmt(f),
+        }
+    }
+}
+
+impl FromStr for DnsServer {
+    type Err = ParseDnsServerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(v) => Ok(DnsServer::SocketAddr(v)),
+            Err(_) => match s.parse() {
+                Ok(v) => Ok(DnsServer::Ip(v)),
+                Err(_) => match s.parse() {
+                    Ok(v) => Ok(DnsServer::Host(v)),
+                    Err(_) => Err(ParseDnsServerError()),
+                },
+            },
+        }
+    }
+}
+
+pub(crate) async fn query(
+    server: &SocketAddr,
+    class: DNSClass,
+    record_type: RecordType,
+    name: &Name,
+    transport: &Transport,
+) -> Result<RData, Error> {
+    if let Some(family) = transport.effective_family() {
+        if family != Family::of(&server.ip()) {
+            return Err(Error::ServerFamily(*server));
+        }
+    }
+    let socket = bind(server, transport)?;
+    socket.connect(server).await?;
+
+    let mut query = Query::query(name.clone(), record_type);
+    query.set_query_class(class);
+    let mut request = Message::new();
+    request
+        .set_id(rand::random())
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(query);
+
+    debug!("querying {} {} {} {}", server, class, record_type, name);
+    socket.send(&request.to_vec()?).await?;
+
+    let mut buf = [0; 4096];
+    let mut response = loop {
+        let len = timeout(TIMEOUT, socket.recv(&mut buf))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "DNS query timed out"))??;
+        let response = Message::from_vec(&buf[..len])?;
+        // ignore anything that isn't an answer to our query
+        if response.id() == request.id() && response.message_type() == MessageType::Response {
+            break response;
+        }
+    };
+    trace!("{:#?}", response);
+
+    response
+        .take_answers()
+        .into_iter()
+        .map(|a| a.into_data())
+        .find(|d| d.to_record_type() == record_type)
+        .ok_or(Error::MissingResponse)
+}
+
+fn bind(server: &SocketAddr, transport: &Transport) -> Result<UdpSocket, io::Error> {
+    let local = transport.bind_addr(server);
+    let socket = Socket::new(Domain::for_address(local), Type::DGRAM, Some(Protocol::UDP))?;
+    if let Some(ref interface) = transport.interface {
+        bind_device(&socket, interface)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&local.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_device(socket: &Socket, interface: &str) -> Result<(), io::Error> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_device(_: &Socket, _: &str) -> Result<(), io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "binding to an interface is not supported on this platform",
+    ))
+}
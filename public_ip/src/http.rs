@@ -0,0 +1,244 @@
+use std::{error::Error as StdError, fmt, io, str::FromStr};
+
+use digest_auth::{AuthContext, HttpMethod};
+use log::debug;
+pub use reqwest::Method;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE},
+    ClientBuilder, Response, StatusCode,
+};
+use url::Url;
+
+use crate::{spec::redact_url, Error, Transport};
+
+#[derive(Debug)]
+pub struct ParseHeaderError();
+
+impl fmt::Display for ParseHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "header must be in the format 'Name: value'")
+    }
+}
+
+impl StdError for ParseHeaderError {}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Header {
+    name: HeaderName,
+    value: HeaderValue,
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.name)?;
+        match self.value.to_str() {
+            Ok(value) => value.fmt(f),
+            Err(_) => write!(f, "{:?}", self.value),
+        }
+    }
+}
+
+impl FromStr for Header {
+    type Err = ParseHeaderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once(':').ok_or(ParseHeaderError())?;
+        let mut value: HeaderValue = value.trim().parse().map_err(|_| ParseHeaderError())?;
+        // headers are often credentials, so keep them out of debug output
+        value.set_sensitive(true);
+        Ok(Header {
+            name: name.trim().parse().map_err(|_| ParseHeaderError())?,
+            value,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Auth {
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Digest {
+        username: String,
+        password: String,
+    },
+    Bearer(String),
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Auth::Digest { username, .. } => f
+                .debug_struct("Digest")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Auth::Bearer(_) => f.debug_tuple("Bearer").field(&"****").finish(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HttpOptions {
+    pub(crate) method: Method,
+    pub(crate) headers: HeaderMap,
+    pub(crate) auth: Option<Auth>,
+    pub(crate) body: Option<String>,
+    pub(crate) accept_invalid_certs: bool,
+}
+
+impl HttpOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn method(mut self, method: Method) -> Self {
+        self.method = method;
+        self
+    }
+
+    pub fn header(mut self, header: Header) -> Self {
+        self.headers.append(header.name, header.value);
+        self
+    }
+
+    pub fn basic_auth<U, P>(mut self, username: U, password: Option<P>) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        self.auth = Some(Auth::Basic {
+            username: username.into(),
+            password: password.map(Into::into),
+        });
+        self
+    }
+
+    /// Answer a digest auth challenge from the server
+    pub fn digest_auth<U, P>(mut self, username: U, password: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        self.auth = Some(Auth::Digest {
+            username: username.into(),
+            password: password.into(),
+        });
+        self
+    }
+
+    pub fn bearer_auth<T>(mut self, token: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.auth = Some(Auth::Bearer(token.into()));
+        self
+    }
+
+    pub fn body<T>(mut self, body: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Don't verify TLS certificates, for LAN devices with self-signed certs
+    pub fn accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+}
+
+pub(crate) async fn request(
+    url: &Url,
+    http: &HttpOptions,
+    transport: &Transport,
+) -> Result<Response, Error> {
+    let mut builder =
+        reqwest::Client::builder().danger_accept_invalid_certs(http.accept_invalid_certs);
+    if let Some(family) = transport.effective_family() {
+        builder =
+            builder.local_address(transport.local_addr.unwrap_or_else(|| family.unspecified()));
+    }
+    if let Some(ref interface) = transport.interface {
+        builder = bind_interface(builder, interface)?;
+    }
+    let client = builder.build()?;
+    let build = |authorization: Option<String>| {
+        let mut request = client
+            .request(http.method.clone(), url.clone())
+            .headers(http.headers.clone());
+        match http.auth {
+            Some(Auth::Basic {
+                ref username,
+                ref password,
+            }) => request = request.basic_auth(username, password.as_ref()),
+            Some(Auth::Bearer(ref token)) => request = request.bearer_auth(token),
+            Some(Auth::Digest { .. }) | None => (),
+        }
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        if let Some(ref body) = http.body {
+            request = request.body(body.clone());
+        }
+        request
+    };
+    // the URL may carry credentials in its userinfo or query string
+    debug!("requesting {} {}", http.method, redact_url(url));
+    let mut res = build(None).send().await?;
+    debug!("got {} response", res.status());
+    if let (StatusCode::UNAUTHORIZED, Some(Auth::Digest { username, password })) =
+        (res.status(), &http.auth)
+    {
+        let challenge = res
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| v.starts_with("Digest"));
+        if let Some(challenge) = challenge {
+            let uri = match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_owned(),
+            };
+            let context = AuthContext::new_with_method(
+                username.as_str(),
+                password.as_str(),
+                uri,
+                http.body.as_ref().map(|b| b.as_bytes()),
+                HttpMethod::from(http.method.as_str()),
+            );
+            let authorization = digest_auth::parse(challenge)?.respond(&context)?;
+            debug!(
+                "retrying {} {} with digest auth",
+                http.method,
+                redact_url(url)
+            );
+            res = build(Some(authorization.to_header_string())).send().await?;
+            debug!("got {} response", res.status());
+        }
+    }
+    if res.status().is_success() {
+        Ok(res)
+    } else {
+        Err(Error::HttpBadResponse(res))
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn bind_interface(builder: ClientBuilder, interface: &str) -> Result<ClientBuilder, io::Error> {
+    Ok(builder.interface(interface))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn bind_interface(_: ClientBuilder, _: &str) -> Result<ClientBuilder, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "binding to an interface is not supported on this platform",
+    ))
+}
@@ -0,0 +1,194 @@
+use std::{
+    error::Error as StdError,
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
+};
+
+use serde_json::Value;
+
+#[derive(Debug)]
+pub enum ParseJsonPathError {
+    Empty,
+    EmptySegment,
+    BadIndex(String),
+}
+
+impl fmt::Display for ParseJsonPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseJsonPathError::Empty => write!(f, "JSON path can not be empty"),
+            ParseJsonPathError::EmptySegment => write!(f, "JSON path has an empty segment"),
+            ParseJsonPathError::BadIndex(s) => write!(f, "bad array index {:?} in JSON path", s),
+        }
+    }
+}
+
+impl StdError for ParseJsonPathError {}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Location of the IP in a JSON response.
+///
+/// Either a JSON Pointer (RFC 6901) like `/data/ip`, or a dotted path with
+/// optional array indices like `data.ip` or `results[0].address`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum JsonPath {
+    Pointer(String),
+    Path(Vec<Segment>),
+}
+
+impl JsonPath {
+    pub(crate) fn find<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        match self {
+            JsonPath::Pointer(pointer) => value.pointer(pointer),
+            JsonPath::Path(segments) => segments.iter().try_fold(value, |v, segment| {
+                match (segment, v) {
+                    (Segment::Key(key), Value::Object(map)) => map.get(key),
+                    // allow `results.0.ip` as well as `results[0].ip`
+                    (Segment::Key(key), Value::Array(vec)) => {
+                        key.parse::<usize>().ok().and_then(|i| vec.get(i))
+                    }
+                    (Segment::Index(i), Value::Array(vec)) => vec.get(*i),
+                    _ => None,
+                }
+            }),
+        }
+    }
+}
+
+/// Interpret a JSON value as an IP address.
+///
+/// Strings are parsed as an address, numbers as an IPv4 address in integer
+/// form, and for arrays the first element that is an address is used.
+pub(crate) fn to_ip(value: &Value) -> Option<IpAddr> {
+    match value {
+        Value::String(s) => s.trim().parse().ok(),
+        Value::Number(n) => n
+            .as_u64()
+            .filter(|n| *n <= u64::from(u32::MAX))
+            .map(|n| Ipv4Addr::from(n as u32).into()),
+        Value::Array(vec) => vec.iter().find_map(to_ip),
+        _ => None,
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonPath::Pointer(pointer) => pointer.fmt(f),
+            JsonPath::Path(segments) => {
+                for (i, segment) in segments.iter().enumerate() {
+                    match segment {
+                        Segment::Key(key) if i == 0 => write!(f, "{}", key)?,
+                        Segment::Key(key) => write!(f, ".{}", key)?,
+                        Segment::Index(index) => write!(f, "[{}]", index)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = ParseJsonPathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ParseJsonPathError::Empty);
+        }
+        if s.starts_with('/') {
+            return Ok(JsonPath::Pointer(s.to_owned()));
+        }
+        let mut segments = Vec::new();
+        for part in s.split('.') {
+            let (key, mut indices) = match part.find('[') {
+                Some(i) => part.split_at(i),
+                None => (part, ""),
+            };
+            if key.is_empty() && (indices.is_empty() || !segments.is_empty()) {
+                return Err(ParseJsonPathError::EmptySegment);
+            }
+            if !key.is_empty() {
+                segments.push(Segment::Key(key.to_owned()));
+            }
+            while !indices.is_empty() {
+                let end = match (indices.starts_with('['), indices.find(']')) {
+                    (true, Some(end)) => end,
+                    _ => return Err(ParseJsonPathError::BadIndex(indices.to_owned())),
+                };
+                let index = indices[1..end]
+                    .parse()
+                    .map_err(|_| ParseJsonPathError::BadIndex(indices[1..end].to_owned()))?;
+                segments.push(Segment::Index(index));
+                indices = &indices[end + 1..];
+            }
+        }
+        Ok(JsonPath::Path(segments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{to_ip, JsonPath, Segment};
+
+    #[test]
+    fn it_parses_key() {
+        assert_eq!(
+            "ip".parse::<JsonPath>().unwrap(),
+            JsonPath::Path(vec![Segment::Key("ip".into())]),
+        );
+    }
+
+    #[test]
+    fn it_parses_dotted_path_with_indices() {
+        assert_eq!(
+            "data.results[0].ip".parse::<JsonPath>().unwrap(),
+            JsonPath::Path(vec![
+                Segment::Key("data".into()),
+                Segment::Key("results".into()),
+                Segment::Index(0),
+                Segment::Key("ip".into()),
+            ]),
+        );
+    }
+
+    #[test]
+    fn it_rejects_bad_paths() {
+        assert!("".parse::<JsonPath>().is_err());
+        assert!("data..ip".parse::<JsonPath>().is_err());
+        assert!("data[x]".parse::<JsonPath>().is_err());
+        assert!("data[0".parse::<JsonPath>().is_err());
+    }
+
+    #[test]
+    fn it_round_trips() {
+        for s in &["ip", "data.ip", "[1].ip", "a[0][1].b", "/data/ip"] {
+            assert_eq!(&s.parse::<JsonPath>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn it_finds_nested_values() {
+        let body = json!({"data": {"ips": ["192.0.2.1"], "n": 3221225985u32}});
+        let ip = "192.0.2.1".parse().unwrap();
+        for s in &[
+            "data.ips[0]",
+            "data.ips.0",
+            "/data/ips/0",
+            "data.ips",
+            "data.n",
+        ] {
+            let path = s.parse::<JsonPath>().unwrap();
+            assert_eq!(path.find(&body).and_then(to_ip), Some(ip), "{}", s);
+        }
+        assert!("data.ip".parse::<JsonPath>().unwrap().find(&body).is_none());
+    }
+}
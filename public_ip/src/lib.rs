@@ -1,32 +1,55 @@
+mod dns;
+mod http;
+mod json_path;
+mod local;
+mod pattern;
+mod preset;
+mod scope;
+mod spec;
+mod tr064;
+mod transport;
+
 use std::{
     error::Error as StdError,
-    fmt,
-    net::{AddrParseError, Ipv4Addr, SocketAddr},
+    fmt, io,
+    net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    process::ExitStatus,
     str::FromStr,
+    time::Duration,
 };
 
 use log::{debug, trace};
 use reqwest::Response;
 use serde_json::Value;
-use tokio::net::UdpSocket;
 pub use trust_dns_client::rr::Name;
 use trust_dns_client::{
-    client::{AsyncClient, ClientHandle},
-    error::ClientError,
     proto::error::ProtoError,
-    rr::{DNSClass, RecordType},
-    udp::UdpClientStream,
+    rr::{DNSClass, RData, RecordType},
 };
 use url::Url;
 
+pub use crate::{
+    dns::DnsServer, http::*, json_path::*, local::shell, pattern::*, preset::*, scope::*, tr064::*,
+    transport::*,
+};
+
 #[derive(Debug)]
 pub enum Error {
     Http(reqwest::Error),
     HttpBadResponse(Response),
+    DigestAuth(digest_auth::Error),
     DnsProto(ProtoError),
-    DnsClient(ClientError),
+    Io(io::Error),
     MissingResponse,
+    MissingJsonPath(JsonPath),
+    BadJsonValue(JsonPath, Value),
     ParseAddr(AddrParseError),
+    UnexpectedAddr(IpAddr),
+    NonGlobalAddr(IpAddr, Scope),
+    ServerFamily(SocketAddr),
+    UnsupportedRecordType(RecordType),
+    CommandFailed(ExitStatus, String),
 }
 
 impl fmt::Display for Error {
@@ -34,10 +57,40 @@ impl fmt::Display for Error {
         match self {
             Error::Http(e) => e.fmt(f),
             Error::HttpBadResponse(res) => write!(f, "Bad response: {}", res.status()),
+            Error::DigestAuth(e) => e.fmt(f),
             Error::DnsProto(e) => e.fmt(f),
-            Error::DnsClient(e) => e.fmt(f),
+            Error::Io(e) => e.fmt(f),
             Error::MissingResponse => write!(f, "IP not found in response"),
+            Error::MissingJsonPath(path) => write!(f, "{} not found in response", path),
+            Error::BadJsonValue(path, value) => {
+                write!(f, "expected IP at {} in response, got {}", path, value)
+            }
             Error::ParseAddr(e) => e.fmt(f),
+            Error::UnexpectedAddr(ip) => write!(f, "unexpected address family: {}", ip),
+            Error::NonGlobalAddr(ip, Scope::SharedNat) => write!(
+                f,
+                "got {}, a carrier-grade NAT address, inbound connections won't reach it",
+                ip
+            ),
+            Error::NonGlobalAddr(ip, scope) => {
+                write!(
+                    f,
+                    "got {}, a {} address rather than a public one",
+                    ip, scope
+                )
+            }
+            Error::ServerFamily(addr) => {
+                write!(f, "DNS server {} doesn't match address family", addr)
+            }
+            Error::UnsupportedRecordType(record_type) => {
+                write!(f, "can't get an IP from a {} record", record_type)
+            }
+            Error::CommandFailed(status, stderr) if stderr.is_empty() => {
+                write!(f, "command failed: {}", status)
+            }
+            Error::CommandFailed(status, stderr) => {
+                write!(f, "command failed: {}: {}", status, stderr)
+            }
         }
     }
 }
@@ -47,10 +100,18 @@ impl StdError for Error {
         match self {
             Error::Http(e) => Some(e),
             Error::HttpBadResponse(_) => None,
+            Error::DigestAuth(e) => Some(e),
             Error::DnsProto(e) => Some(e),
-            Error::DnsClient(e) => Some(e),
+            Error::Io(e) => Some(e),
             Error::MissingResponse => None,
+            Error::MissingJsonPath(_) => None,
+            Error::BadJsonValue(_, _) => None,
             Error::ParseAddr(e) => Some(e),
+            Error::UnexpectedAddr(_) => None,
+            Error::NonGlobalAddr(_, _) => None,
+            Error::ServerFamily(_) => None,
+            Error::UnsupportedRecordType(_) => None,
+            Error::CommandFailed(_, _) => None,
         }
     }
 }
@@ -67,15 +128,21 @@ impl From<reqwest::Error> for Error {
     }
 }
 
+impl From<digest_auth::Error> for Error {
+    fn from(e: digest_auth::Error) -> Self {
+        Error::DigestAuth(e)
+    }
+}
+
 impl From<ProtoError> for Error {
     fn from(e: ProtoError) -> Self {
         Error::DnsProto(e)
     }
 }
 
-impl From<ClientError> for Error {
-    fn from(e: ClientError) -> Self {
-        Error::DnsClient(e)
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
     }
 }
 
@@ -90,9 +157,10 @@ impl fmt::Display for ParseRecordTypeError {
 
 impl StdError for ParseRecordTypeError {}
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum DnsRecordType {
     A,
+    AAAA,
     TXT,
 }
 
@@ -102,121 +170,391 @@ impl FromStr for DnsRecordType {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "A" => Ok(DnsRecordType::A),
+            "AAAA" => Ok(DnsRecordType::AAAA),
             "TXT" => Ok(DnsRecordType::TXT),
             _ => Err(ParseRecordTypeError()),
         }
     }
 }
 
+#[derive(Debug)]
+pub struct ParseDnsClassError();
+
+impl fmt::Display for ParseDnsClassError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unsupported DNS class")
+    }
+}
+
+impl StdError for ParseDnsClassError {}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum DnsClass {
+    IN,
+    CH,
+}
+
+impl FromStr for DnsClass {
+    type Err = ParseDnsClassError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "IN" => Ok(DnsClass::IN),
+            "CH" => Ok(DnsClass::CH),
+            _ => Err(ParseDnsClassError()),
+        }
+    }
+}
+
+impl From<DnsClass> for DNSClass {
+    fn from(val: DnsClass) -> Self {
+        match val {
+            DnsClass::IN => Self::IN,
+            DnsClass::CH => Self::CH,
+        }
+    }
+}
+
 impl From<DnsRecordType> for RecordType {
     fn from(val: DnsRecordType) -> Self {
         match val {
             DnsRecordType::A => Self::A,
+            DnsRecordType::AAAA => Self::AAAA,
             DnsRecordType::TXT => Self::TXT,
         }
     }
 }
 
+/// A source of our public IP address.
+///
+/// Can be parsed from, and displayed as, a spec string such as `opendns`,
+/// `json:ip https://api.ipify.org/?format=json`, or
+/// `@resolver1.opendns.com A myip.opendns.com`. HTTP options, the transport
+/// and command timeouts aren't part of the spec.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Service {
+    Preset {
+        preset: Preset,
+        http: HttpOptions,
+        transport: Transport,
+    },
     PlainText {
         url: Url,
+        http: HttpOptions,
+        transport: Transport,
     },
     Json {
         url: Url,
-        key: String,
+        path: JsonPath,
+        http: HttpOptions,
+        transport: Transport,
+    },
+    Regex {
+        url: Url,
+        pattern: Pattern,
+        http: HttpOptions,
+        transport: Transport,
     },
     Dns {
-        server: SocketAddr,
-        record_type: RecordType,
+        server: DnsServer,
+        class: DNSClass,
+        /// Defaults to A, or AAAA when looking up an IPv6 address
+        record_type: Option<RecordType>,
         name: Name,
+        transport: Transport,
+    },
+    Tr064(Tr064),
+    Exec {
+        command: String,
+        timeout: Duration,
+    },
+    File {
+        path: PathBuf,
     },
 }
 
 impl Service {
+    pub fn preset(preset: Preset) -> Service {
+        Service::Preset {
+            preset,
+            http: Default::default(),
+            transport: Default::default(),
+        }
+    }
+
     pub fn plain_text(url: Url) -> Service {
-        Service::PlainText { url }
+        Service::PlainText {
+            url,
+            http: Default::default(),
+            transport: Default::default(),
+        }
+    }
+
+    pub fn json(url: Url, path: JsonPath) -> Service {
+        Service::Json {
+            url,
+            path,
+            http: Default::default(),
+            transport: Default::default(),
+        }
     }
 
-    pub fn json(url: Url, key: String) -> Service {
-        Service::Json { url, key }
+    pub fn regex(url: Url, pattern: Pattern) -> Service {
+        Service::Regex {
+            url,
+            pattern,
+            http: Default::default(),
+            transport: Default::default(),
+        }
     }
 
-    pub fn dns(server: SocketAddr, record_type: DnsRecordType, name: Name) -> Service {
+    pub fn dns<S>(server: S, record_type: DnsRecordType, name: Name) -> Service
+    where
+        S: Into<DnsServer>,
+    {
         Service::Dns {
-            server,
-            record_type: record_type.into(),
+            server: server.into(),
+            class: DNSClass::IN,
+            record_type: Some(record_type.into()),
             name,
+            transport: Default::default(),
+        }
+    }
+
+    pub fn tr064(url: Url) -> Service {
+        Service::Tr064(Tr064::new(url))
+    }
+
+    pub fn exec(command: String) -> Service {
+        Service::Exec {
+            command,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    pub fn file(path: PathBuf) -> Service {
+        Service::File { path }
+    }
+
+    /// Set the transport, has no effect on local services
+    pub fn with_transport(mut self, val: Transport) -> Service {
+        match self {
+            Service::Preset {
+                ref mut transport, ..
+            }
+            | Service::PlainText {
+                ref mut transport, ..
+            }
+            | Service::Json {
+                ref mut transport, ..
+            }
+            | Service::Regex {
+                ref mut transport, ..
+            }
+            | Service::Dns {
+                ref mut transport, ..
+            } => *transport = val,
+            Service::Tr064(ref mut router) => router.transport = val,
+            Service::Exec { .. } | Service::File { .. } => (),
+        }
+        self
+    }
+
+    /// Set the DNS class to query, has no effect on non-DNS services
+    pub fn with_class(mut self, val: DnsClass) -> Service {
+        if let Service::Dns { ref mut class, .. } = self {
+            *class = val.into();
         }
+        self
+    }
+
+    /// Set HTTP options, has no effect on non-HTTP services
+    pub fn with_http(mut self, val: HttpOptions) -> Service {
+        match self {
+            Service::Preset { ref mut http, .. }
+            | Service::PlainText { ref mut http, .. }
+            | Service::Json { ref mut http, .. }
+            | Service::Regex { ref mut http, .. } => *http = val,
+            Service::Tr064(ref mut router) => router.http = val,
+            Service::Dns { .. } | Service::Exec { .. } | Service::File { .. } => (),
+        }
+        self
+    }
+
+    /// Look up our IP, refusing private, CGNAT, and other non-global
+    /// addresses with [`Error::NonGlobalAddr`]
+    pub async fn ip(&self) -> Result<IpAddr, Error> {
+        global(self.ip_any_scope(None).await?)
     }
 
     pub async fn ipv4(&self) -> Result<Ipv4Addr, Error> {
+        match global(self.ip_any_scope(Some(Family::V4)).await?)? {
+            IpAddr::V4(ip) => Ok(ip),
+            ip => Err(Error::UnexpectedAddr(ip)),
+        }
+    }
+
+    pub async fn ipv6(&self) -> Result<Ipv6Addr, Error> {
+        match global(self.ip_any_scope(Some(Family::V6)).await?)? {
+            IpAddr::V6(ip) => Ok(ip),
+            ip => Err(Error::UnexpectedAddr(ip)),
+        }
+    }
+
+    /// Look up our IP whatever its scope, for when a non-global address is
+    /// expected, e.g. a LAN that is the "public" side of a private network
+    pub async fn ip_any_scope(&self, family: Option<Family>) -> Result<IpAddr, Error> {
+        let ip = self.lookup(family).await?;
+        match family {
+            Some(family) if family != Family::of(&ip) => Err(Error::UnexpectedAddr(ip)),
+            _ => Ok(ip),
+        }
+    }
+
+    // HTTP sources will answer with the address the request came from, so
+    // unless the transport is pinned connect using the family we're after
+    async fn lookup(&self, family: Option<Family>) -> Result<IpAddr, Error> {
         match self {
-            Service::PlainText { url } => {
-                let body = get(url).await?.text().await?;
+            Service::Preset {
+                preset,
+                http,
+                transport,
+            } => {
+                let service = preset
+                    .service(family.unwrap_or(Family::V4))
+                    .with_http(http.clone())
+                    .with_transport(transport.clone());
+                Box::pin(service.lookup(family)).await
+            }
+            Service::PlainText {
+                url,
+                http,
+                transport,
+            } => {
+                let transport = match family {
+                    Some(family) => transport.or_family(family),
+                    None => transport.clone(),
+                };
+                let body = http::request(url, http, &transport).await?.text().await?;
                 trace!("response body: {}", body);
                 Ok(body.trim_end().parse()?)
             }
-            Service::Json { url, key } => {
-                let body = get(url).await?.json::<Value>().await?;
+            Service::Json {
+                url,
+                path,
+                http,
+                transport,
+            } => {
+                let transport = match family {
+                    Some(family) => transport.or_family(family),
+                    None => transport.clone(),
+                };
+                let body = http::request(url, http, &transport)
+                    .await?
+                    .json::<Value>()
+                    .await?;
                 trace!("response body: {}", body);
-                Ok(body
-                    .get(&key)
-                    .and_then(|v| v.as_str())
-                    .ok_or(Error::MissingResponse)?
-                    .parse()?)
+                let value = path
+                    .find(&body)
+                    .ok_or_else(|| Error::MissingJsonPath(path.clone()))?;
+                json_path::to_ip(value)
+                    .ok_or_else(|| Error::BadJsonValue(path.clone(), value.clone()))
+            }
+            Service::Regex {
+                url,
+                pattern,
+                http,
+                transport,
+            } => {
+                let transport = match family {
+                    Some(family) => transport.or_family(family),
+                    None => transport.clone(),
+                };
+                let body = http::request(url, http, &transport).await?.text().await?;
+                trace!("response body: {}", body);
+                pattern.find_ip(&body, family).ok_or(Error::MissingResponse)
             }
             Service::Dns {
                 server,
+                class,
                 record_type,
                 name,
+                transport,
             } => {
-                let stream = UdpClientStream::<UdpSocket>::new(server.clone());
-                let (mut client, bg) = AsyncClient::connect(stream).await?;
-                tokio::spawn(bg);
-                debug!("querying {} {} {} {}", server, DNSClass::IN, record_type, &name);
-                let mut response = client
-                    .query(name.clone(), DNSClass::IN, *record_type)
+                let record_type = record_type.unwrap_or(match family {
+                    Some(Family::V6) => RecordType::AAAA,
+                    _ => RecordType::A,
+                });
+                if !matches!(
+                    record_type,
+                    RecordType::A | RecordType::AAAA | RecordType::TXT
+                ) {
+                    return Err(Error::UnsupportedRecordType(record_type));
+                }
+                // a host name may resolve to either family, so pick one that
+                // matches the transport, or failing that what we're after
+                let server = server
+                    .socket_addr(transport.effective_family().or(family))
                     .await?;
-                trace!("{:#?}", response);
-                let rdata = match response.take_answers().into_iter().next() {
-                    Some(a) => a.into_data(),
-                    None => return Err(Error::MissingResponse),
-                };
+                let rdata = dns::query(&server, *class, record_type, name, transport).await?;
                 debug!("got result {:?}", rdata);
-                let ip = match record_type {
-                    RecordType::A => rdata.into_a().expect("expected A record"),
-                    RecordType::TXT => rdata
-                        .into_txt()
-                        .expect("expected TXT record")
-                        .to_string()
-                        .parse()?,
-                    _ => panic!("{} not implemented", record_type),
+                let ip = match rdata {
+                    RData::A(ip) => ip.into(),
+                    RData::AAAA(ip) => ip.into(),
+                    RData::TXT(txt) => txt.to_string().parse()?,
+                    _ => return Err(Error::UnsupportedRecordType(record_type)),
                 };
                 Ok(ip)
             }
+            // the router reports its WAN address, whichever way we connect
+            Service::Tr064(router) => match family {
+                Some(Family::V4) => router.ipv4().await.map(Into::into),
+                Some(Family::V6) => router.ipv6().await.map(Into::into),
+                None => match router.ipv4().await {
+                    Err(Error::MissingResponse) => router.ipv6().await.map(Into::into),
+                    result => result.map(Into::into),
+                },
+            },
+            Service::Exec { command, timeout } => local::exec(command, *timeout, family).await,
+            Service::File { path } => local::read(path, family).await,
         }
     }
 }
 
+#[allow(clippy::result_large_err)]
+fn global(ip: IpAddr) -> Result<IpAddr, Error> {
+    match Scope::of(&ip) {
+        Scope::Global => Ok(ip),
+        scope => Err(Error::NonGlobalAddr(ip, scope)),
+    }
+}
+
 impl Default for Service {
     fn default() -> Service {
-        Service::Dns {
-            server: ([208, 67, 222, 222], 53).into(), // resolver1.opendns.com
-            record_type: RecordType::A,
-            name: "myip.opendns.com"
-                .parse()
-                .expect("hardcoded name shouldn't fail to parse"),
-        }
+        Service::preset(Preset::OpenDns)
     }
 }
 
-async fn get(url: &Url) -> Result<Response, Error> {
-    debug!("requesting {}", url);
-    let res = reqwest::get(url.clone()).await?;
-    debug!("got {} response", res.status());
-    if res.status().is_success() {
-        Ok(res)
-    } else {
-        Err(Error::HttpBadResponse(res))
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use trust_dns_client::rr::{DNSClass, RecordType};
+
+    use super::{Error, Service};
+
+    #[tokio::test]
+    async fn it_rejects_record_types_without_an_ip() {
+        let service = Service::Dns {
+            server: "192.0.2.53".parse::<IpAddr>().unwrap().into(),
+            class: DNSClass::IN,
+            record_type: Some(RecordType::MX),
+            name: "example.com".parse().unwrap(),
+            transport: Default::default(),
+        };
+        match service.ip().await {
+            Err(Error::UnsupportedRecordType(RecordType::MX)) => (),
+            other => panic!("expected unsupported record type, got {:?}", other),
+        }
     }
 }
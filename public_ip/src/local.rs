@@ -0,0 +1,95 @@
+use std::{io, net::IpAddr, path::Path, process::Stdio, time::Duration};
+
+use log::{debug, trace};
+use tokio::{fs, process::Command, time::timeout};
+
+use crate::{Error, Family};
+
+pub(crate) async fn exec(
+    command: &str,
+    limit: Duration,
+    family: Option<Family>,
+) -> Result<IpAddr, Error> {
+    debug!("running {:?}", command);
+    let child = shell(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let output = timeout(limit, child.wait_with_output())
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "command timed out"))??;
+    debug!("command exited with {}", output.status);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    trace!("command output: {}", stdout);
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        return Err(Error::CommandFailed(output.status, stderr));
+    }
+    first_ip(&stdout, family).ok_or(Error::MissingResponse)
+}
+
+/// A command to run `command` with the platform's shell, `sh -c`, or `cmd /C`
+/// on Windows.
+#[cfg(not(windows))]
+pub fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+/// A command to run `command` with the platform's shell, `sh -c`, or `cmd /C`
+/// on Windows.
+#[cfg(windows)]
+pub fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+pub(crate) async fn read(path: &Path, family: Option<Family>) -> Result<IpAddr, Error> {
+    debug!("reading {}", path.display());
+    let contents = fs::read_to_string(path).await?;
+    trace!("file contents: {}", contents);
+    first_ip(&contents, family).ok_or(Error::MissingResponse)
+}
+
+/// Find the first address in some free-form text, such as `ip addr` output
+/// or a `KEY=value` file written by a DHCP hook.
+pub(crate) fn first_ip(text: &str, family: Option<Family>) -> Option<IpAddr> {
+    text.split(|c: char| c.is_whitespace() || "=,;\"'()[]<>".contains(c))
+        .filter_map(|word| {
+            // allow address/prefix, e.g. 192.0.2.1/24
+            let word = word.split('/').next().unwrap_or(word);
+            word.parse().ok()
+        })
+        .find(|ip| family.is_none() || family == Some(Family::of(ip)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::first_ip;
+    use crate::Family;
+
+    #[test]
+    fn it_finds_first_ip() {
+        assert_eq!(
+            first_ip("192.0.2.1\n", None),
+            Some("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(
+            first_ip("WAN_IP=\"192.0.2.1\"\nWAN_IP6=2001:db8::1/64\n", None),
+            Some("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(first_ip("no address here", None), None);
+    }
+
+    #[test]
+    fn it_filters_by_family() {
+        assert_eq!(
+            first_ip("inet 192.0.2.1/24 inet6 2001:db8::1/64", Some(Family::V6)),
+            Some("2001:db8::1".parse().unwrap())
+        );
+    }
+}
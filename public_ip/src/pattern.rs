@@ -0,0 +1,89 @@
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use regex::Regex;
+
+use crate::Family;
+
+/// Regular expression to pull an IP out of a page.
+///
+/// The address is taken from the capture group named `ip`, or failing that
+/// the first capture group, or the whole match if there are no groups.
+#[derive(Clone, Debug)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    // the first match that is an address (of the requested family, if any)
+    pub(crate) fn find_ip(&self, text: &str, family: Option<Family>) -> Option<IpAddr> {
+        self.0
+            .captures_iter(text)
+            .filter_map(|captures| {
+                captures
+                    .name("ip")
+                    .or_else(|| captures.get(1))
+                    .or_else(|| captures.get(0))
+            })
+            .filter_map(|m| m.as_str().trim().parse().ok())
+            .find(|ip| family.is_none() || family == Some(Family::of(ip)))
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.as_str().fmt(f)
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pattern;
+    use crate::Family;
+
+    const PAGE: &str = r#"
+        <tr><td>LAN IP</td><td>192.168.1.1</td></tr>
+        <tr><td>WAN IP</td><td>192.0.2.1</td></tr>
+        <tr><td>WAN IPv6</td><td>2001:db8::1</td></tr>
+    "#;
+
+    #[test]
+    fn it_uses_capture_group() {
+        let pattern = r"WAN IP</td><td>([^<]+)".parse::<Pattern>().unwrap();
+        assert_eq!(
+            pattern.find_ip(PAGE, None),
+            Some("192.0.2.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn it_prefers_named_group() {
+        let pattern = r"(WAN) IP</td><td>(?P<ip>[^<]+)"
+            .parse::<Pattern>()
+            .unwrap();
+        assert_eq!(
+            pattern.find_ip(PAGE, None),
+            Some("192.0.2.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn it_filters_by_family() {
+        let pattern = r"WAN IP(?:v6)?</td><td>([^<]+)".parse::<Pattern>().unwrap();
+        assert_eq!(
+            pattern.find_ip(PAGE, Some(Family::V6)),
+            Some("2001:db8::1".parse().unwrap())
+        );
+    }
+}
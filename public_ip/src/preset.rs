@@ -0,0 +1,187 @@
+use std::{
+    error::Error as StdError,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+use url::Url;
+
+use crate::{DnsClass, DnsRecordType, Family, Service};
+
+#[derive(Debug)]
+pub struct ParsePresetError();
+
+impl fmt::Display for ParsePresetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown preset")
+    }
+}
+
+impl StdError for ParsePresetError {}
+
+/// Well known public IP services.
+///
+/// Each has an endpoint per address family, so that looking up an IPv6
+/// address talks to the service over IPv6.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Preset {
+    OpenDns,
+    GoogleDns,
+    CloudflareDns,
+    Ipify,
+    Icanhazip,
+    Akamai,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 6] = [
+        Preset::OpenDns,
+        Preset::GoogleDns,
+        Preset::CloudflareDns,
+        Preset::Ipify,
+        Preset::Icanhazip,
+        Preset::Akamai,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::OpenDns => "opendns",
+            Preset::GoogleDns => "google-dns",
+            Preset::CloudflareDns => "cloudflare-dns",
+            Preset::Ipify => "ipify",
+            Preset::Icanhazip => "icanhazip",
+            Preset::Akamai => "akamai",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Preset::OpenDns => "A/AAAA record for myip.opendns.com from resolver1.opendns.com",
+            Preset::GoogleDns => "TXT record for o-o.myaddr.l.google.com from ns1.google.com",
+            Preset::CloudflareDns => "CHAOS TXT record for whoami.cloudflare from 1.1.1.1",
+            Preset::Ipify => "JSON from api.ipify.org or api6.ipify.org",
+            Preset::Icanhazip => "plain text from ipv4.icanhazip.com or ipv6.icanhazip.com",
+            Preset::Akamai => "plain text from whatismyip.akamai.com",
+        }
+    }
+
+    pub fn service(self, family: Family) -> Service {
+        match (self, family) {
+            (Preset::OpenDns, Family::V4) => dns(
+                Ipv4Addr::new(208, 67, 222, 222),
+                DnsRecordType::A,
+                "myip.opendns.com",
+            ),
+            (Preset::OpenDns, Family::V6) => dns(
+                Ipv6Addr::new(0x2620, 0x119, 0x35, 0, 0, 0, 0, 0x35),
+                DnsRecordType::AAAA,
+                "myip.opendns.com",
+            ),
+            (Preset::GoogleDns, Family::V4) => dns(
+                Ipv4Addr::new(216, 239, 32, 10),
+                DnsRecordType::TXT,
+                "o-o.myaddr.l.google.com",
+            ),
+            (Preset::GoogleDns, Family::V6) => dns(
+                Ipv6Addr::new(0x2001, 0x4860, 0x4802, 0x32, 0, 0, 0, 0xa),
+                DnsRecordType::TXT,
+                "o-o.myaddr.l.google.com",
+            ),
+            (Preset::CloudflareDns, Family::V4) => dns(
+                Ipv4Addr::new(1, 1, 1, 1),
+                DnsRecordType::TXT,
+                "whoami.cloudflare",
+            )
+            .with_class(DnsClass::CH),
+            (Preset::CloudflareDns, Family::V6) => dns(
+                Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111),
+                DnsRecordType::TXT,
+                "whoami.cloudflare",
+            )
+            .with_class(DnsClass::CH),
+            (Preset::Ipify, Family::V4) => Service::json(
+                url("https://api.ipify.org/?format=json"),
+                "ip".parse()
+                    .expect("hardcoded path shouldn't fail to parse"),
+            ),
+            (Preset::Ipify, Family::V6) => Service::json(
+                url("https://api6.ipify.org/?format=json"),
+                "ip".parse()
+                    .expect("hardcoded path shouldn't fail to parse"),
+            ),
+            (Preset::Icanhazip, Family::V4) => {
+                Service::plain_text(url("https://ipv4.icanhazip.com/"))
+            }
+            (Preset::Icanhazip, Family::V6) => {
+                Service::plain_text(url("https://ipv6.icanhazip.com/"))
+            }
+            (Preset::Akamai, Family::V4) => {
+                Service::plain_text(url("http://whatismyip.akamai.com/"))
+            }
+            (Preset::Akamai, Family::V6) => {
+                Service::plain_text(url("http://ipv6.whatismyip.akamai.com/"))
+            }
+        }
+    }
+}
+
+fn dns<T: Into<IpAddr>>(server: T, record_type: DnsRecordType, name: &str) -> Service {
+    Service::dns(
+        SocketAddr::new(server.into(), 53),
+        record_type,
+        name.parse()
+            .expect("hardcoded name shouldn't fail to parse"),
+    )
+}
+
+fn url(s: &str) -> Url {
+    s.parse().expect("hardcoded URL shouldn't fail to parse")
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name().fmt(f)
+    }
+}
+
+impl FromStr for Preset {
+    type Err = ParsePresetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Preset::ALL
+            .iter()
+            .copied()
+            .find(|preset| preset.name() == s)
+            .ok_or(ParsePresetError())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Preset;
+    use crate::{DnsServer, Family, Service};
+
+    #[test]
+    fn it_round_trips_names() {
+        for preset in &Preset::ALL {
+            assert_eq!(preset.to_string().parse::<Preset>().unwrap(), *preset);
+        }
+        assert!("nope".parse::<Preset>().is_err());
+    }
+
+    #[test]
+    fn it_uses_dns_server_of_family() {
+        for preset in &Preset::ALL {
+            for family in &[Family::V4, Family::V6] {
+                if let Service::Dns {
+                    server: DnsServer::SocketAddr(server),
+                    ..
+                } = preset.service(*family)
+                {
+                    assert_eq!(Family::of(&server.ip()), *family, "{}", preset);
+                }
+            }
+        }
+    }
+}
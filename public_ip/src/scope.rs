@@ -0,0 +1,139 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+/// Where an address is routable, to catch IP sources that report an address
+/// that isn't our public one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scope {
+    Global,
+    Unspecified,
+    Loopback,
+    /// RFC 1918 IPv4 ranges, and IPv6 unique local addresses
+    Private,
+    /// Carrier-grade NAT, 100.64.0.0/10
+    SharedNat,
+    LinkLocal,
+    Documentation,
+    Benchmarking,
+    Multicast,
+    Broadcast,
+    /// Reserved or otherwise unallocated ranges, i.e. bogons
+    Reserved,
+}
+
+impl Scope {
+    pub fn of(ip: &IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(ip) => v4(ip),
+            IpAddr::V6(ip) => v6(ip),
+        }
+    }
+
+    pub fn is_global(self) -> bool {
+        self == Scope::Global
+    }
+}
+
+fn v4(ip: &Ipv4Addr) -> Scope {
+    let [a, b, c, _] = ip.octets();
+    match (a, b, c) {
+        _ if ip.is_unspecified() => Scope::Unspecified,
+        _ if ip.is_broadcast() => Scope::Broadcast,
+        (0, _, _) => Scope::Reserved,
+        (10, _, _) | (172, 16..=31, _) | (192, 168, _) => Scope::Private,
+        (100, 64..=127, _) => Scope::SharedNat,
+        (127, _, _) => Scope::Loopback,
+        (169, 254, _) => Scope::LinkLocal,
+        (192, 0, 0) => Scope::Reserved,
+        (192, 0, 2) | (198, 51, 100) | (203, 0, 113) => Scope::Documentation,
+        (198, 18..=19, _) => Scope::Benchmarking,
+        (224..=239, _, _) => Scope::Multicast,
+        (240..=255, _, _) => Scope::Reserved,
+        _ => Scope::Global,
+    }
+}
+
+fn v6(ip: &Ipv6Addr) -> Scope {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return v4(&ip);
+    }
+    let segments = ip.segments();
+    match segments {
+        _ if ip.is_unspecified() => Scope::Unspecified,
+        _ if ip.is_loopback() => Scope::Loopback,
+        [0x2001, 0xdb8, ..] => Scope::Documentation,
+        [0x2001, 0x2, 0, ..] => Scope::Benchmarking,
+        [0x100, 0, 0, 0, ..] => Scope::Reserved,
+        [s, ..] if s & 0xfe00 == 0xfc00 => Scope::Private,
+        [s, ..] if s & 0xffc0 == 0xfe80 => Scope::LinkLocal,
+        [s, ..] if s & 0xff00 == 0xff00 => Scope::Multicast,
+        // only 2000::/3 is allocated for global unicast
+        [s, ..] if s & 0xe000 == 0x2000 => Scope::Global,
+        _ => Scope::Reserved,
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Global => write!(f, "global"),
+            Scope::Unspecified => write!(f, "unspecified"),
+            Scope::Loopback => write!(f, "loopback"),
+            Scope::Private => write!(f, "private"),
+            Scope::SharedNat => write!(f, "carrier-grade NAT"),
+            Scope::LinkLocal => write!(f, "link-local"),
+            Scope::Documentation => write!(f, "documentation"),
+            Scope::Benchmarking => write!(f, "benchmarking"),
+            Scope::Multicast => write!(f, "multicast"),
+            Scope::Broadcast => write!(f, "broadcast"),
+            Scope::Reserved => write!(f, "reserved"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scope;
+
+    #[test]
+    fn it_classifies_ipv4() {
+        for (ip, scope) in &[
+            ("0.0.0.0", Scope::Unspecified),
+            ("10.1.2.3", Scope::Private),
+            ("172.31.0.1", Scope::Private),
+            ("192.168.1.1", Scope::Private),
+            ("100.64.0.1", Scope::SharedNat),
+            ("100.128.0.1", Scope::Global),
+            ("127.0.0.1", Scope::Loopback),
+            ("169.254.0.1", Scope::LinkLocal),
+            ("192.0.2.1", Scope::Documentation),
+            ("203.0.113.7", Scope::Documentation),
+            ("198.18.0.1", Scope::Benchmarking),
+            ("224.0.0.1", Scope::Multicast),
+            ("240.0.0.1", Scope::Reserved),
+            ("255.255.255.255", Scope::Broadcast),
+            ("8.8.8.8", Scope::Global),
+        ] {
+            assert_eq!(Scope::of(&ip.parse().unwrap()), *scope, "{}", ip);
+        }
+    }
+
+    #[test]
+    fn it_classifies_ipv6() {
+        for (ip, scope) in &[
+            ("::", Scope::Unspecified),
+            ("::1", Scope::Loopback),
+            ("::ffff:192.168.1.1", Scope::Private),
+            ("fd00::1", Scope::Private),
+            ("fe80::1", Scope::LinkLocal),
+            ("2001:db8::1", Scope::Documentation),
+            ("ff02::1", Scope::Multicast),
+            ("4000::1", Scope::Reserved),
+            ("2620:119:35::35", Scope::Global),
+        ] {
+            assert_eq!(Scope::of(&ip.parse().unwrap()), *scope, "{}", ip);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use log::trace;
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use url::Url;
+
+use crate::{http, Auth, Error, HttpOptions, Method, Transport};
+
+/// A SOAP service on the router, and the control URL its actions go to, the
+/// two have to match or the router rejects the action
+struct Service {
+    urn: &'static str,
+    control_path: &'static str,
+}
+
+const WAN_IP: Service = Service {
+    urn: "urn:dslforum-org:service:WANIPConnection:1",
+    control_path: "/upnp/control/wanipconnection1",
+};
+
+// DSL/PPPoE lines are on WANPPPConnection rather than WANIPConnection
+const WAN_PPP: Service = Service {
+    urn: "urn:dslforum-org:service:WANPPPConnection:1",
+    control_path: "/upnp/control/wanpppconn1",
+};
+
+// AVM's IPv6 actions are only on the IGD (UPnP) interface, which needs no auth
+const IGD_WAN_IP: Service = Service {
+    urn: "urn:schemas-upnp-org:service:WANIPConnection:1",
+    control_path: "/igdupnp/control/WANIPConn1",
+};
+
+/// WAN addresses as reported by a TR-064 router.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WanAddresses {
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    pub ipv6_prefix: Option<(Ipv6Addr, u8)>,
+}
+
+/// A router exposing the TR-064 SOAP interface, such as an AVM FRITZ!Box,
+/// usually at `http://fritz.box:49000`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tr064 {
+    pub(crate) url: Url,
+    pub(crate) http: HttpOptions,
+    pub(crate) transport: Transport,
+}
+
+impl Tr064 {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            http: Default::default(),
+            transport: Default::default(),
+        }
+    }
+
+    /// Set HTTP options, TR-064 always uses digest auth so basic auth
+    /// credentials will be used to answer the digest challenge
+    pub fn with_http(mut self, http: HttpOptions) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub async fn ipv4(&self) -> Result<Ipv4Addr, Error> {
+        match self.external_ipv4(&WAN_IP).await {
+            Err(Error::HttpBadResponse(_)) | Err(Error::MissingResponse) => {
+                self.external_ipv4(&WAN_PPP).await
+            }
+            result => result,
+        }
+    }
+
+    async fn external_ipv4(&self, service: &Service) -> Result<Ipv4Addr, Error> {
+        let body = self.call(service, "GetExternalIPAddress").await?;
+        Ok(element(&body, "NewExternalIPAddress")
+            .ok_or(Error::MissingResponse)?
+            .parse()?)
+    }
+
+    pub async fn ipv6(&self) -> Result<Ipv6Addr, Error> {
+        let body = self
+            .call(&IGD_WAN_IP, "X_AVM_DE_GetExternalIPv6Address")
+            .await?;
+        Ok(element(&body, "NewExternalIPv6Address")
+            .ok_or(Error::MissingResponse)?
+            .parse()?)
+    }
+
+    pub async fn ipv6_prefix(&self) -> Result<(Ipv6Addr, u8), Error> {
+        let body = self.call(&IGD_WAN_IP, "X_AVM_DE_GetIPv6Prefix").await?;
+        let prefix = element(&body, "NewIPv6Prefix")
+            .ok_or(Error::MissingResponse)?
+            .parse()?;
+        let len = element(&body, "NewPrefixLength")
+            .and_then(|len| len.parse().ok())
+            .ok_or(Error::MissingResponse)?;
+        Ok((prefix, len))
+    }
+
+    /// Fetch all WAN addresses, those the router doesn't have (e.g. no IPv4
+    /// on a DS-Lite line) are left as `None`.
+    pub async fn addresses(&self) -> Result<WanAddresses, Error> {
+        Ok(WanAddresses {
+            ipv4: missing_as_none(self.ipv4().await)?,
+            ipv6: missing_as_none(self.ipv6().await)?,
+            ipv6_prefix: missing_as_none(self.ipv6_prefix().await)?,
+        })
+    }
+
+    async fn call(&self, service: &Service, action: &str) -> Result<String, Error> {
+        let mut url = self.url.clone();
+        url.set_path(service.control_path);
+        let mut http = self
+            .http
+            .clone()
+            .method(Method::POST)
+            .body(envelope(service, action));
+        if let Some(Auth::Basic { username, password }) = http.auth.take() {
+            http = http.digest_auth(username, password.unwrap_or_default());
+        }
+        http.headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("text/xml; charset=\"utf-8\""),
+        );
+        http.headers.insert(
+            "SoapAction",
+            format!("{}#{}", service.urn, action)
+                .parse()
+                .expect("action should be a valid header value"),
+        );
+        let body = http::request(&url, &http, &self.transport)
+            .await?
+            .text()
+            .await?;
+        trace!("response body: {}", body);
+        Ok(body)
+    }
+}
+
+fn envelope(service: &Service, action: &str) -> String {
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" "#,
+            r#"s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+            r#"<s:Body><u:{action} xmlns:u="{service}"/></s:Body>"#,
+            r#"</s:Envelope>"#,
+        ),
+        action = action,
+        service = service.urn,
+    )
+}
+
+// the responses are simple enough that pulling out the text of an element by
+// name is all that's needed, an empty element is treated as missing
+fn element<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let start = body.find(&format!("<{}>", name))? + name.len() + 2;
+    let len = body[start..].find(&format!("</{}>", name))?;
+    Some(body[start..start + len].trim()).filter(|s| !s.is_empty())
+}
+
+#[allow(clippy::result_large_err)]
+fn missing_as_none<T>(result: Result<T, Error>) -> Result<Option<T>, Error> {
+    match result {
+        Ok(v) => Ok(Some(v)),
+        Err(Error::MissingResponse) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::{Tr064, WanAddresses};
+    use crate::HttpOptions;
+
+    // minimal stand-in for a FRITZ!Box, checks each action is sent with the
+    // service URN matching its control URL, challenges TR-064 requests without
+    // an Authorization header, and answers the rest based on the SoapAction.
+    // With `ppp` it acts like a DSL box, with no WANIPConnection
+    async fn serve(listener: TcpListener, ppp: bool) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut headers = HashMap::new();
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let path = line.split(' ').nth(1).unwrap().to_owned();
+            let (urn, tr064) = match path.as_str() {
+                "/upnp/control/wanipconnection1" => {
+                    ("urn:dslforum-org:service:WANIPConnection:1", true)
+                }
+                "/upnp/control/wanpppconn1" => {
+                    ("urn:dslforum-org:service:WANPPPConnection:1", true)
+                }
+                "/igdupnp/control/WANIPConn1" => {
+                    ("urn:schemas-upnp-org:service:WANIPConnection:1", false)
+                }
+                _ => panic!("unexpected path {}", path),
+            };
+            loop {
+                line.clear();
+                stream.read_line(&mut line).await.unwrap();
+                match line.trim_end().split_once(": ") {
+                    Some((k, v)) => headers.insert(k.to_lowercase(), v.to_owned()),
+                    None => break,
+                };
+            }
+            let len = headers["content-length"].parse().unwrap();
+            let mut body = vec![0; len];
+            stream.read_exact(&mut body).await.unwrap();
+            let body = String::from_utf8(body).unwrap();
+            assert!(body.contains("<s:Envelope"));
+            assert!(body.contains(&format!("xmlns:u=\"{}\"", urn)));
+            let (service, action) = headers["soapaction"].split_once('#').unwrap();
+            assert_eq!(service, urn);
+            assert!(body.contains(&format!("<u:{} ", action)));
+
+            let response = match headers.get("authorization") {
+                None if tr064 => concat!(
+                    "HTTP/1.1 401 Unauthorized\r\n",
+                    "WWW-Authenticate: Digest realm=\"HTTPS Access\", ",
+                    "nonce=\"F758BA5F2AA9C2DE\", algorithm=MD5, qop=\"auth\"\r\n",
+                    "Content-Length: 0\r\n\r\n",
+                )
+                .to_owned(),
+                auth => {
+                    if tr064 {
+                        let auth = auth.unwrap();
+                        assert!(auth.starts_with("Digest "));
+                        assert!(auth.contains("username=\"admin\""));
+                    }
+                    let args = match action {
+                        "GetExternalIPAddress" if ppp && urn.contains("WANIPConnection") => None,
+                        "GetExternalIPAddress" => {
+                            Some("<NewExternalIPAddress>192.0.2.1</NewExternalIPAddress>")
+                        }
+                        "X_AVM_DE_GetExternalIPv6Address" => Some(concat!(
+                            "<NewExternalIPv6Address>2001:db8::1</NewExternalIPv6Address>",
+                            "<NewPrefixLength>64</NewPrefixLength>",
+                        )),
+                        "X_AVM_DE_GetIPv6Prefix" => Some(concat!(
+                            "<NewIPv6Prefix>2001:db8:1::</NewIPv6Prefix>",
+                            "<NewPrefixLength>56</NewPrefixLength>",
+                        )),
+                        _ => panic!("unexpected action {}", action),
+                    };
+                    match args {
+                        Some(args) => {
+                            let body = format!(
+                                concat!(
+                                    "<?xml version=\"1.0\"?><s:Envelope><s:Body>",
+                                    "<u:{0}Response>{1}</u:{0}Response>",
+                                    "</s:Body></s:Envelope>",
+                                ),
+                                action, args
+                            );
+                            format!(
+                                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                                body.len(),
+                                body
+                            )
+                        }
+                        // UPnPError 401 Invalid Action
+                        None => "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
+                            .to_owned(),
+                    }
+                }
+            };
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    async fn addresses(ppp: bool) -> WanAddresses {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener, ppp));
+
+        let router = Tr064::new(url.parse().unwrap())
+            .with_http(HttpOptions::new().basic_auth("admin", Some("secret")));
+        router.addresses().await.unwrap()
+    }
+
+    fn expected() -> WanAddresses {
+        WanAddresses {
+            ipv4: Some("192.0.2.1".parse().unwrap()),
+            ipv6: Some("2001:db8::1".parse().unwrap()),
+            ipv6_prefix: Some(("2001:db8:1::".parse().unwrap(), 56)),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_gets_wan_addresses() {
+        assert_eq!(addresses(false).await, expected());
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_to_ppp_on_dsl_lines() {
+        assert_eq!(addresses(true).await, expected());
+    }
+}
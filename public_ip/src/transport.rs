@@ -0,0 +1,114 @@
+use std::{
+    error::Error as StdError,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+#[derive(Debug)]
+pub struct ParseFamilyError();
+
+impl fmt::Display for ParseFamilyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "address family must be one of ipv4, ipv6, 4, or 6")
+    }
+}
+
+impl StdError for ParseFamilyError {}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    pub fn of(ip: &IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => Family::V4,
+            IpAddr::V6(_) => Family::V6,
+        }
+    }
+
+    /// The unspecified address, `0.0.0.0` or `::`, to bind to for this family
+    pub fn unspecified(self) -> IpAddr {
+        match self {
+            Family::V4 => Ipv4Addr::UNSPECIFIED.into(),
+            Family::V6 => Ipv6Addr::UNSPECIFIED.into(),
+        }
+    }
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Family::V4 => write!(f, "ipv4"),
+            Family::V6 => write!(f, "ipv6"),
+        }
+    }
+}
+
+impl FromStr for Family {
+    type Err = ParseFamilyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "4" | "ipv4" | "v4" => Ok(Family::V4),
+            "6" | "ipv6" | "v6" => Ok(Family::V6),
+            _ => Err(ParseFamilyError()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Transport {
+    pub(crate) family: Option<Family>,
+    pub(crate) local_addr: Option<IpAddr>,
+    pub(crate) interface: Option<String>,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn family(mut self, family: Family) -> Self {
+        self.family = Some(family);
+        self
+    }
+
+    pub fn local_addr(mut self, addr: IpAddr) -> Self {
+        self.local_addr = Some(addr);
+        self
+    }
+
+    /// Bind to the named network interface with `SO_BINDTODEVICE` (Linux only)
+    pub fn interface<T>(mut self, interface: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.interface = Some(interface.into());
+        self
+    }
+
+    // a local address implies its family, and wins over an explicit family
+    pub(crate) fn effective_family(&self) -> Option<Family> {
+        self.local_addr.as_ref().map(Family::of).or(self.family)
+    }
+
+    pub(crate) fn or_family(&self, family: Family) -> Self {
+        let mut transport = self.clone();
+        if transport.effective_family().is_none() {
+            transport.family = Some(family);
+        }
+        transport
+    }
+
+    pub(crate) fn bind_addr(&self, server: &SocketAddr) -> SocketAddr {
+        let ip = match self.local_addr {
+            Some(ip) => ip,
+            None => Family::of(&server.ip()).unspecified(),
+        };
+        (ip, 0).into()
+    }
+}
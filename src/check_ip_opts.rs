@@ -1,251 +1,293 @@
-use std::{
-    error::Error as StdError,
-    fmt, io,
-    net::{IpAddr, SocketAddr},
-    str::FromStr,
-};
-
-use nom::{
-    branch::alt,
-    bytes::complete::{is_not, tag},
-    character::complete::{space0, space1},
-    combinator::{all_consuming, map, map_res, opt, rest},
-    sequence::{preceded, terminated, tuple},
-    IResult,
-};
-use public_ip::{DnsRecordType, Name};
-use tokio::net::lookup_host;
-use url::Url;
+use std::{convert::Infallible, error::Error as StdError, fmt, net::IpAddr, str::FromStr};
+
+use public_ip::{Family, Header, HttpOptions, Method, Service, Transport};
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use crate::config::{option_from_str, vec_from_str};
 
 #[derive(Debug)]
-pub struct ParseServerError();
+pub struct ParseCredentialsError();
 
-#[derive(Debug, PartialEq)]
-pub enum Server {
-    Host(Name),
-    Ip(IpAddr),
-    SocketAddr(SocketAddr),
+impl fmt::Display for ParseCredentialsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "username can not be empty")
+    }
 }
 
-impl Server {
-    async fn into_socket_addr(self) -> Result<SocketAddr, io::Error> {
-        match self {
-            Server::Host(name) => lookup_host((name.to_string().as_ref(), 53))
-                .await?
-                .next()
-                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "could not resolve host")),
-            Server::Ip(ip) => Ok((ip, 53).into()),
-            Server::SocketAddr(addr) => Ok(addr),
-        }
+impl StdError for ParseCredentialsError {}
+
+#[derive(Clone)]
+pub struct Credentials {
+    username: String,
+    password: Option<String>,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "****"))
+            .finish()
     }
 }
 
-impl FromStr for Server {
-    type Err = ParseServerError;
+impl FromStr for Credentials {
+    type Err = ParseCredentialsError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.parse() {
-            Ok(v) => Ok(Server::SocketAddr(v)),
-            Err(_) => match s.parse() {
-                Ok(v) => Ok(Server::Ip(v)),
-                Err(_) => match s.parse() {
-                    Ok(v) => Ok(Server::Host(v)),
-                    Err(_) => Err(ParseServerError()),
-                },
-            },
+        let (username, password) = match s.split_once(':') {
+            Some((username, password)) => (username, Some(password.to_owned())),
+            None => (s, None),
+        };
+        if username.is_empty() {
+            return Err(ParseCredentialsError());
         }
+        Ok(Credentials {
+            username: username.to_owned(),
+            password,
+        })
     }
 }
 
-#[derive(Debug)]
-pub struct ParseIpOptsError();
+#[derive(Clone)]
+pub struct BearerToken(String);
 
-impl fmt::Display for ParseIpOptsError {
+impl fmt::Debug for BearerToken {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "bad options format")
+        f.debug_tuple("BearerToken").field(&"****").finish()
     }
 }
 
-impl StdError for ParseIpOptsError {}
-
-#[derive(Debug, PartialEq)]
-pub enum CheckIpOpts {
-    PlainText {
-        url: Url,
-    },
-    Json {
-        url: Url,
-        key: String,
-    },
-    Dns {
-        server: Server,
-        record_type: Option<DnsRecordType>,
-        name: Name,
-    },
-}
+impl FromStr for BearerToken {
+    type Err = Infallible;
 
-impl CheckIpOpts {
-    pub async fn into_service(self) -> Result<public_ip::Service, io::Error> {
-        match self {
-            CheckIpOpts::PlainText { url } => Ok(public_ip::Service::plain_text(url)),
-            CheckIpOpts::Json { url, key } => Ok(public_ip::Service::json(url, key)),
-            CheckIpOpts::Dns {
-                server,
-                record_type,
-                name,
-            } => server.into_socket_addr().await.map(|server| {
-                public_ip::Service::dns(server, record_type.unwrap_or(DnsRecordType::A), name)
-            }),
-        }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(BearerToken(s.to_owned()))
     }
 }
 
-impl FromStr for CheckIpOpts {
-    type Err = ParseIpOptsError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match ip_opts(s) {
-            Ok((_, opts)) => Ok(opts),
-            Err(_) => Err(ParseIpOptsError()),
+// The options for how to reach an IP source, defined once for `check-ip`
+// and the config file, and again with a prefix for `update --preflight-*`,
+// as structopt can't prefix flattened options. `conflicts` are the
+// arguments the options can't be given with.
+macro_rules! source_opts {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident,
+        prefix = $prefix:literal,
+        conflicts = [$($conflict:literal),*]
+        $(, into = $into:ident)?
+    ) => {
+        $(#[$attr])*
+        #[derive(StructOpt, Clone, Debug, Default, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        pub struct $name {
+            /// Address family to look up, and connect to the IP service with
+            #[structopt(
+                name = concat!($prefix, "family"),
+                long = concat!($prefix, "family"),
+                conflicts_with_all = &[$($conflict),*],
+            )]
+            #[serde(skip)]
+            pub family: Option<Family>,
+            /// Local address to connect to the IP service from
+            #[structopt(
+                name = concat!($prefix, "bind"),
+                long = concat!($prefix, "bind"),
+                conflicts_with_all = &[$($conflict),*],
+            )]
+            #[serde(default, deserialize_with = "option_from_str")]
+            pub bind: Option<IpAddr>,
+            /// Network interface to connect to the IP service through
+            #[structopt(
+                name = concat!($prefix, "interface"),
+                long = concat!($prefix, "interface"),
+                conflicts_with_all = &[$($conflict),*],
+            )]
+            pub interface: Option<String>,
+            /// HTTP method to request HTTP sources with
+            #[structopt(
+                name = concat!($prefix, "method"),
+                long = concat!($prefix, "method"),
+                conflicts_with_all = &[$($conflict),*],
+            )]
+            #[serde(default, deserialize_with = "option_from_str")]
+            pub method: Option<Method>,
+            /// Extra header to send to HTTP sources, as 'Name: value'
+            #[structopt(
+                name = concat!($prefix, "header"),
+                long = concat!($prefix, "header"),
+                number_of_values = 1,
+                conflicts_with_all = &[$($conflict),*],
+            )]
+            #[serde(default, deserialize_with = "vec_from_str")]
+            pub headers: Vec<Header>,
+            /// Basic auth for HTTP sources, as 'username[:password]'
+            #[structopt(
+                name = concat!($prefix, "user"),
+                long = concat!($prefix, "user"),
+                conflicts_with_all = &[$($conflict),*],
+            )]
+            #[serde(default, deserialize_with = "option_from_str")]
+            pub user: Option<Credentials>,
+            /// Bearer token auth for HTTP sources
+            #[structopt(
+                name = concat!($prefix, "bearer"),
+                long = concat!($prefix, "bearer"),
+                conflicts_with_all = &[concat!($prefix, "user"), $($conflict),*],
+            )]
+            #[serde(default, deserialize_with = "option_from_str")]
+            pub bearer: Option<BearerToken>,
+            /// Request body to send to HTTP sources, implies POST
+            #[structopt(
+                name = concat!($prefix, "data"),
+                long = concat!($prefix, "data"),
+                conflicts_with_all = &[$($conflict),*],
+            )]
+            pub data: Option<String>,
+            /// Accept invalid TLS certificates from HTTP sources
+            #[structopt(
+                name = concat!($prefix, "insecure"),
+                long = concat!($prefix, "insecure"),
+                conflicts_with_all = &[$($conflict),*],
+            )]
+            #[serde(default)]
+            pub insecure: bool,
+            /// Accept private, CGNAT, and other non-global addresses from the IP source
+            #[structopt(
+                name = concat!($prefix, "allow-non-global"),
+                long = concat!($prefix, "allow-non-global"),
+                conflicts_with_all = &[$($conflict),*],
+            )]
+            #[serde(skip)]
+            pub allow_non_global: bool,
         }
-    }
-}
 
-fn ip_opts(i: &str) -> IResult<&str, CheckIpOpts> {
-    all_consuming(alt((
-        map(json, |(key, url)| CheckIpOpts::Json {
-            url,
-            key: key.to_owned(),
-        }),
-        map(dns, |(server, record_type, name)| CheckIpOpts::Dns {
-            server,
-            record_type,
-            name,
-        }),
-        map(url, |url| CheckIpOpts::PlainText { url }),
-    )))(i)
-}
+        $(
+            impl From<$name> for $into {
+                fn from(val: $name) -> Self {
+                    Self {
+                        family: val.family,
+                        bind: val.bind,
+                        interface: val.interface,
+                        method: val.method,
+                        headers: val.headers,
+                        user: val.user,
+                        bearer: val.bearer,
+                        data: val.data,
+                        insecure: val.insecure,
+                        allow_non_global: val.allow_non_global,
+                    }
+                }
+            }
 
-fn url(i: &str) -> IResult<&str, Url> {
-    map_res(rest, |i: &str| i.parse())(i)
+            impl From<$into> for $name {
+                fn from(val: $into) -> Self {
+                    Self {
+                        family: val.family,
+                        bind: val.bind,
+                        interface: val.interface,
+                        method: val.method,
+                        headers: val.headers,
+                        user: val.user,
+                        bearer: val.bearer,
+                        data: val.data,
+                        insecure: val.insecure,
+                        allow_non_global: val.allow_non_global,
+                    }
+                }
+            }
+        )?
+    };
 }
 
-fn json(i: &str) -> IResult<&str, (&str, Url)> {
-    preceded(
-        terminated(tag("json:"), space0),
-        tuple((terminated(is_not(" \t"), space1), url)),
-    )(i)
+// how to reach an IP source, as given to `check-ip` or in a config group's
+// `source_options`. Not a doc comment, as structopt would use it to describe
+// the subcommands it's flattened into
+source_opts! {
+    pub struct SourceOpts,
+    prefix = "",
+    conflicts = []
 }
 
-fn dns(i: &str) -> IResult<&str, (Server, Option<DnsRecordType>, Name)> {
-    tuple((
-        preceded(
-            tag("@"),
-            terminated(map_res(is_not(" \t"), |i: &str| i.parse()), space1),
-        ),
-        opt(terminated(
-            map_res(is_not(" \t"), |i: &str| i.parse()),
-            space1,
-        )),
-        map_res(rest, |i: &str| i.parse()),
-    ))(i)
+// how to reach the IP source `update` checks before updating
+source_opts! {
+    pub struct PreflightOpts,
+    prefix = "preflight-",
+    conflicts = ["ip", "ipv6"],
+    into = SourceOpts
 }
 
-#[cfg(test)]
-mod tests {
-    use public_ip::DnsRecordType;
-
-    use super::{CheckIpOpts, Server};
-
-    #[test]
-    fn it_parses_url_as_plain_text() {
-        assert_eq!(
-            "http://example.com/".parse::<CheckIpOpts>().unwrap(),
-            CheckIpOpts::PlainText {
-                url: "http://example.com/".parse().unwrap()
-            },
-        );
-    }
-
-    #[test]
-    fn it_parses_json() {
-        assert_eq!(
-            "json:ip http://example.com/"
-                .parse::<CheckIpOpts>()
-                .unwrap(),
-            CheckIpOpts::Json {
-                url: "http://example.com/".parse().unwrap(),
-                key: "ip".into(),
-            },
-        );
-    }
-
-    #[test]
-    fn it_parses_json_with_space() {
-        assert_eq!(
-            "json: ip http://example.com/"
-                .parse::<CheckIpOpts>()
-                .unwrap(),
-            CheckIpOpts::Json {
-                url: "http://example.com/".parse().unwrap(),
-                key: "ip".into(),
-            },
-        );
-    }
+/// A service to look up our IP with, along with the address family wanted
+pub struct IpLookup {
+    service: Service,
+    family: Option<Family>,
+    allow_non_global: bool,
+}
 
-    #[test]
-    fn it_parses_dns_host() {
-        assert_eq!(
-            "@example.com A ip.example.com"
-                .parse::<CheckIpOpts>()
-                .unwrap(),
-            CheckIpOpts::Dns {
-                server: Server::Host("example.com".parse().unwrap()),
-                record_type: Some(DnsRecordType::A),
-                name: "ip.example.com".parse().unwrap(),
-            },
-        );
+impl IpLookup {
+    pub fn new(service: Option<Service>, opts: SourceOpts) -> Self {
+        let family = opts.bind.as_ref().map(Family::of).or(opts.family);
+        let mut transport = Transport::new();
+        if let Some(family) = family {
+            transport = transport.family(family);
+        }
+        if let Some(bind) = opts.bind {
+            transport = transport.local_addr(bind);
+        }
+        if let Some(interface) = opts.interface {
+            transport = transport.interface(interface);
+        }
+        let mut http = HttpOptions::new().accept_invalid_certs(opts.insecure);
+        // like curl, sending data implies POST
+        match (opts.method, opts.data.is_some()) {
+            (Some(method), _) => http = http.method(method),
+            (None, true) => http = http.method(Method::POST),
+            (None, false) => (),
+        }
+        for header in opts.headers {
+            http = http.header(header);
+        }
+        if let Some(user) = opts.user {
+            http = http.basic_auth(user.username, user.password);
+        }
+        if let Some(BearerToken(token)) = opts.bearer {
+            http = http.bearer_auth(token);
+        }
+        if let Some(data) = opts.data {
+            http = http.body(data);
+        }
+        Self {
+            service: service
+                .unwrap_or_default()
+                .with_transport(transport)
+                .with_http(http),
+            family,
+            allow_non_global: opts.allow_non_global,
+        }
     }
 
-    #[test]
-    fn it_parses_dns_ip() {
-        assert_eq!(
-            "@192.0.2.1 A ip.example.com"
-                .parse::<CheckIpOpts>()
-                .unwrap(),
-            CheckIpOpts::Dns {
-                server: Server::Ip("192.0.2.1".parse().unwrap()),
-                record_type: Some(DnsRecordType::A),
-                name: "ip.example.com".parse().unwrap(),
-            },
-        );
+    /// The address family that will be looked up
+    pub fn family(&self) -> Family {
+        self.family.unwrap_or(Family::V4)
     }
 
-    #[test]
-    fn it_parses_dns_socket_addr() {
-        assert_eq!(
-            "@192.0.2.1:53 A ip.example.com"
-                .parse::<CheckIpOpts>()
-                .unwrap(),
-            CheckIpOpts::Dns {
-                server: Server::SocketAddr("192.0.2.1:53".parse().unwrap()),
-                record_type: Some(DnsRecordType::A),
-                name: "ip.example.com".parse().unwrap(),
-            },
-        );
+    /// The IP source, as it would be given on the command line but without
+    /// credentials, query strings, or command arguments, as it's reported on
+    /// the unauthenticated /metrics and /status pages
+    pub fn source(&self) -> String {
+        self.service.redacted()
     }
 
-    #[test]
-    fn it_parses_dns_without_record_type() {
-        assert_eq!(
-            "@example.com ip.example.com"
-                .parse::<CheckIpOpts>()
-                .unwrap(),
-            CheckIpOpts::Dns {
-                server: Server::Host("example.com".parse().unwrap()),
-                record_type: None,
-                name: "ip.example.com".parse().unwrap(),
-            },
-        );
+    pub async fn ip(&self) -> Result<IpAddr, public_ip::Error> {
+        let family = self.family();
+        if self.allow_non_global {
+            return self.service.ip_any_scope(Some(family)).await;
+        }
+        match family {
+            Family::V4 => self.service.ipv4().await.map(IpAddr::V4),
+            Family::V6 => self.service.ipv6().await.map(IpAddr::V6),
+        }
     }
 }
@@ -1,21 +1,98 @@
-use std::{error::Error as StdError, net::Ipv4Addr};
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display},
+    net::IpAddr,
+};
 
+use log::warn;
+use public_ip::{Family, Preset, Scope, Service};
+use serde::Serialize;
 use structopt::StructOpt;
 
-use crate::check_ip_opts::CheckIpOpts;
+use crate::check_ip_opts::{IpLookup, SourceOpts};
 
 #[derive(StructOpt, Debug)]
 pub struct CheckIp {
+    /// IP source, a preset name, URL, 'json:<path> <url>', '@server [type] name', etc.
     #[structopt(short, long)]
-    pub opts: Option<CheckIpOpts>,
+    pub opts: Option<Service>,
+    /// List the preset IP sources and exit
+    #[structopt(long)]
+    pub list_presets: bool,
+    #[structopt(flatten)]
+    pub source: SourceOpts,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PresetInfo {
+    name: String,
+    description: &'static str,
+}
+
+/// An address looked up, and how
+#[derive(Debug, Serialize)]
+pub struct IpCheck {
+    ip: IpAddr,
+    family: String,
+    scope: String,
+    /// the IP source that answered
+    source: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum CheckIpOutput {
+    Presets(Vec<PresetInfo>),
+    Ip(IpCheck),
+}
+
+impl Display for CheckIpOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckIpOutput::Presets(presets) => {
+                for (i, preset) in presets.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{:<16}{}", preset.name, preset.description)?;
+                }
+                Ok(())
+            }
+            CheckIpOutput::Ip(check) => check.ip.fmt(f),
+        }
+    }
 }
 
 impl CheckIp {
-    pub async fn run(self) -> Result<Ipv4Addr, Box<dyn StdError>> {
-        let service = match self.opts {
-            Some(opts) => opts.into_service().await?,
-            None => Default::default(),
+    pub async fn run(self) -> Result<CheckIpOutput, Box<dyn StdError>> {
+        if self.list_presets {
+            let presets = Preset::ALL
+                .iter()
+                .map(|preset| PresetInfo {
+                    name: preset.to_string(),
+                    description: preset.description(),
+                })
+                .collect();
+            return Ok(CheckIpOutput::Presets(presets));
+        }
+        let lookup = IpLookup::new(self.opts, self.source);
+        // a CGNAT address is still worth reporting when checking, but with a
+        // warning shown at the default verbosity
+        let ip = match lookup.ip().await {
+            Err(public_ip::Error::NonGlobalAddr(ip, Scope::SharedNat)) => ip,
+            result => result?,
         };
-        Ok(service.ipv4().await?)
+        if Scope::of(&ip) == Scope::SharedNat {
+            warn!(
+                "{} is a carrier-grade NAT address, inbound connections won't reach this host",
+                ip
+            );
+        }
+        Ok(CheckIpOutput::Ip(IpCheck {
+            ip,
+            family: Family::of(&ip).to_string(),
+            scope: Scope::of(&ip).to_string(),
+            source: lookup.source(),
+        }))
     }
 }
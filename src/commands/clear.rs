@@ -1,31 +1,95 @@
-use std::{error::Error as StdError, fmt::Display};
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display},
+};
 
-use duck_dns::{ClearOptions, ClearTxtOptions, Client};
+use duck_dns::{ClearOptions, ClearTxtOptions, Client, Response, Status, TxtResponse, Updated};
+use serde::Serialize;
 use structopt::StructOpt;
 
-use crate::opts::Account;
+use crate::{
+    dry_run::DryRun,
+    opts::{Account, StateOpts},
+    state::StateFile,
+};
 
 #[derive(StructOpt, Debug)]
 pub struct Clear {
     #[structopt(short = "x", long)]
     pub txt: bool,
+    /// Print the request that would be sent, without sending it
+    #[structopt(long)]
+    pub dry_run: bool,
     #[structopt(flatten)]
     pub account: Account,
+    #[structopt(flatten)]
+    pub state: StateOpts,
     #[structopt(skip)]
     pub verbose: bool,
 }
 
+/// The response to clearing either addresses or TXT
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum ClearResponse {
+    Ip(Response),
+    Txt(TxtResponse),
+}
+
+impl ClearResponse {
+    pub fn status(&self) -> Status {
+        match self {
+            ClearResponse::Ip(r) => r.status(),
+            ClearResponse::Txt(r) => r.status(),
+        }
+    }
+
+    pub fn updated(&self) -> Option<Updated> {
+        match self {
+            ClearResponse::Ip(r) => r.updated(),
+            ClearResponse::Txt(r) => r.updated(),
+        }
+    }
+}
+
+impl Display for ClearResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClearResponse::Ip(r) => r.fmt(f),
+            ClearResponse::Txt(r) => r.fmt(f),
+        }
+    }
+}
+
 impl Clear {
-    pub async fn run(self) -> Result<Box<dyn Display>, Box<dyn StdError>> {
+    pub async fn run(self) -> Result<ClearResponse, Box<dyn StdError>> {
+        let state_file = StateFile::from(self.state);
+        let domains = self.account.domain.clone();
         let client = Client::from(self.account);
         if self.txt {
-            Ok(Box::new(
-                client.clear_txt(ClearTxtOptions::new(self.verbose)).await?,
-            ))
+            let response = client.clear_txt(ClearTxtOptions::new(self.verbose)).await?;
+            if response.status() == Status::Ok {
+                state_file.modify(|state| state.clear_txt(&domains));
+            }
+            Ok(ClearResponse::Txt(response))
         } else {
-            Ok(Box::new(
-                client.clear(ClearOptions::new(self.verbose)).await?,
-            ))
+            let response = client.clear(ClearOptions::new(self.verbose)).await?;
+            if response.status() == Status::Ok {
+                state_file.modify(|state| state.clear_ip(&domains));
+            }
+            Ok(ClearResponse::Ip(response))
         }
     }
+
+    /// The request `run` would send, without sending it. What DuckDNS had
+    /// before isn't recorded, so whether it'd change isn't known
+    pub fn plan(self) -> DryRun {
+        let client = Client::from(self.account);
+        let request = if self.txt {
+            client.dry_run_clear_txt(ClearTxtOptions::new(self.verbose))
+        } else {
+            client.dry_run_clear(ClearOptions::new(self.verbose))
+        };
+        DryRun::new(request, None, None)
+    }
 }
@@ -0,0 +1,263 @@
+use std::{
+    collections::BTreeMap,
+    error::Error as StdError,
+    fmt,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use futures_util::future::join_all;
+use log::{error, info, warn};
+use structopt::StructOpt;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{broadcast, mpsc},
+    time::interval,
+};
+
+use crate::{
+    commands::update::{NoConfigError, Update},
+    config::{Config, ConfigError},
+    control::{Control, Cycle, Event, Wake},
+    hooks::HookOpts,
+    metrics::{self, Metrics, MetricsOpts},
+    notify::Notifier,
+    opts::StateOpts,
+    parse_duration::parse_duration,
+    pidfile::Pidfile,
+    server::{self, Page},
+};
+
+#[derive(Debug)]
+struct GroupsStoppedError();
+
+impl fmt::Display for GroupsStoppedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "every group stopped updating")
+    }
+}
+
+impl StdError for GroupsStoppedError {}
+
+/// Keep every group in the --config file updated.
+///
+/// SIGTERM or SIGINT stop after any update in progress, SIGHUP reloads the
+/// config, and SIGUSR1 checks and updates every group straight away. Under
+/// systemd readiness, status, and watchdog pings are sent with sd_notify.
+#[derive(StructOpt, Debug)]
+pub struct Daemon {
+    /// Schedule for groups that don't set their own
+    #[structopt(short, long, default_value = "5m", parse(try_from_str = parse_duration))]
+    pub schedule: Duration,
+    /// Lock, and write our process ID to, this file, refusing to start if
+    /// another instance holds it
+    #[structopt(long, parse(from_os_str))]
+    pub pidfile: Option<PathBuf>,
+    #[structopt(flatten)]
+    pub state: StateOpts,
+    #[structopt(flatten)]
+    pub metrics: MetricsOpts,
+    /// Hooks for groups that don't set their own
+    #[structopt(flatten)]
+    pub hooks: HookOpts,
+    /// Serve /healthz, /status, and /metrics from this address
+    #[structopt(long)]
+    pub status_listen: Option<SocketAddr>,
+    /// /healthz fails once a group has gone this long without a successful
+    /// update or check
+    #[structopt(long, default_value = "1h", parse(try_from_str = parse_duration))]
+    pub stale_after: Duration,
+    #[structopt(skip)]
+    pub config: Option<PathBuf>,
+    #[structopt(skip)]
+    pub verbose: bool,
+}
+
+impl Daemon {
+    fn load(&self, path: &Path) -> Result<Vec<(String, Update)>, ConfigError> {
+        let mut updates = Config::load(path)?.updates(&self.state, &self.hooks, self.verbose)?;
+        for (_, update) in &mut updates {
+            update.schedule.get_or_insert(self.schedule);
+        }
+        Ok(updates)
+    }
+
+    pub async fn run(self) -> Result<(), Box<dyn StdError>> {
+        let path = self.config.clone().ok_or(NoConfigError())?;
+        let _pidfile = match self.pidfile {
+            Some(ref path) => Some(Pidfile::lock(path)?),
+            None => None,
+        };
+        let metrics = if self.metrics.is_enabled() || self.status_listen.is_some() {
+            Some(Metrics::start(&self.metrics).await?)
+        } else {
+            None
+        };
+        if let (Some(addr), Some(metrics)) = (self.status_listen, metrics.clone()) {
+            let listener = server::bind(addr).await?;
+            tokio::spawn(server::serve(
+                listener,
+                status_pages(metrics, self.stale_after),
+            ));
+        }
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut user1 = signal(SignalKind::user_defined1())?;
+
+        let notifier = Notifier::from_env();
+        let watchdog = notifier.watchdog();
+        // ping twice per interval, as systemd recommends
+        let mut watchdog_ticker = interval(watchdog.map_or(Duration::from_secs(60), |d| d / 2));
+        let (events_tx, mut events) = mpsc::unbounded_channel();
+        let mut progress = Progress::default();
+
+        let mut updates = self.load(&path)?;
+        loop {
+            info!("updating {} groups", updates.len());
+            if let Some(ref metrics) = metrics {
+                let names = updates
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>();
+                metrics.retain(&names);
+                for (name, update) in &mut updates {
+                    update.group_metrics = metrics.group(name, &update.domain);
+                }
+            }
+            let (wake, _) = broadcast::channel(16);
+            let groups = join_all(updates.into_iter().map(|(name, update)| {
+                let control = Control::new(name.clone(), wake.subscribe(), events_tx.clone());
+                async move { (name, update.run_with(Some(control)).await) }
+            }));
+            tokio::pin!(groups);
+            if progress.ready {
+                notifier.notify("READY=1");
+            }
+
+            let reload = loop {
+                tokio::select! {
+                    // scheduled updates only finish early if they fail to start,
+                    // so without a signal to stop there's nothing left to do
+                    results = &mut groups => {
+                        log_failures(results);
+                        return Err(GroupsStoppedError().into());
+                    }
+                    Some(event) = events.recv() => progress.event(event, &notifier),
+                    _ = watchdog_ticker.tick(), if watchdog.is_some() => {
+                        match progress.stalled(watchdog.unwrap_or_default()) {
+                            Some(name) => warn!("group {} is stalled, not pinging watchdog", name),
+                            None => notifier.notify("WATCHDOG=1"),
+                        }
+                    }
+                    _ = terminate.recv() => break None,
+                    _ = interrupt.recv() => break None,
+                    _ = hangup.recv() => match self.load(&path) {
+                        Ok(updates) => break Some(updates),
+                        Err(e) => error!("not reloading config, {}", e),
+                    },
+                    _ = user1.recv() => {
+                        info!("checking now");
+                        let _ = wake.send(Wake::Check);
+                    }
+                }
+            };
+
+            info!("stopping updates");
+            notifier.notify(if reload.is_some() {
+                "RELOADING=1"
+            } else {
+                "STOPPING=1"
+            });
+            let _ = wake.send(Wake::Stop);
+            log_failures(groups.await);
+            // forget the old groups, and anything they reported while stopping
+            while events.try_recv().is_ok() {}
+            progress.reset();
+            match reload {
+                Some(reloaded) => {
+                    info!("reloaded {}", path.display());
+                    updates = reloaded;
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+/// What the groups are up to, reported to the service manager
+#[derive(Debug, Default)]
+struct Progress {
+    ready: bool,
+    busy: BTreeMap<String, Instant>,
+    last: BTreeMap<String, Cycle>,
+}
+
+impl Progress {
+    fn event(&mut self, event: Event, notifier: &Notifier) {
+        match event {
+            Event::Started(name) => {
+                self.busy.insert(name, Instant::now());
+            }
+            Event::Finished(name, cycle) => {
+                self.busy.remove(&name);
+                // ready once anything has worked, a group that keeps failing
+                // shouldn't stop the others from starting
+                if !self.ready && !cycle.is_failed() {
+                    self.ready = true;
+                    notifier.notify("READY=1");
+                }
+                self.last.insert(name, cycle);
+                notifier.notify(&format!("STATUS={}", self.status()));
+            }
+        }
+    }
+
+    /// A group that's been mid-cycle for longer than `limit`
+    fn stalled(&self, limit: Duration) -> Option<&str> {
+        self.busy
+            .iter()
+            .find(|(_, since)| since.elapsed() > limit)
+            .map(|(name, _)| name.as_str())
+    }
+
+    fn status(&self) -> String {
+        self.last
+            .iter()
+            .map(|(name, cycle)| format!("{} {}", name, cycle).replace('\n', " "))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    fn reset(&mut self) {
+        self.busy.clear();
+        self.last.clear();
+    }
+}
+
+fn status_pages(metrics: Metrics, stale_after: Duration) -> impl Fn(&str) -> Option<Page> {
+    move |path| match path {
+        "/healthz" => match metrics.stale(stale_after).as_slice() {
+            [] => Some(Page::ok("text/plain", "ok\n".to_owned())),
+            stale => Some(Page {
+                status: "503 Service Unavailable",
+                content_type: "text/plain",
+                body: format!("stale: {}\n", stale.join(", ")),
+            }),
+        },
+        "/status" => Some(Page::ok("application/json", metrics.status(stale_after))),
+        "/metrics" => Some(Page::ok(metrics::CONTENT_TYPE, metrics.render())),
+        _ => None,
+    }
+}
+
+type GroupResult = (String, Result<Vec<duck_dns::Response>, Box<dyn StdError>>);
+
+fn log_failures(results: Vec<GroupResult>) {
+    for (name, result) in results {
+        if let Err(e) = result {
+            error!("group {}: {}", name, e);
+        }
+    }
+}
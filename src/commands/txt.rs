@@ -1,25 +1,61 @@
 use std::error::Error as StdError;
 
-use duck_dns::{Client, TxtOptions};
+use duck_dns::{Client, Status, TxtOptions, TxtResponse, Updated};
 use structopt::StructOpt;
 
-use crate::opts::Account;
+use crate::{
+    dry_run::DryRun,
+    opts::{Account, StateOpts},
+    state::StateFile,
+};
 
 #[derive(StructOpt, Debug)]
 pub struct Txt {
     #[structopt(short = "x", long)]
     pub txt: String,
+    /// Print the request that would be sent, and whether it's expected to
+    /// change anything, without sending it
+    #[structopt(long)]
+    pub dry_run: bool,
     #[structopt(flatten)]
     pub account: Account,
+    #[structopt(flatten)]
+    pub state: StateOpts,
     #[structopt(skip)]
     pub verbose: bool,
 }
 
 impl Txt {
-    pub async fn run(self) -> Result<duck_dns::TxtResponse, Box<dyn StdError>> {
+    pub async fn run(self) -> Result<TxtResponse, Box<dyn StdError>> {
+        let state_file = StateFile::from(self.state);
+        let domains = self.account.domain.clone();
         let client = Client::from(self.account);
-        Ok(client
-            .update_txt(TxtOptions::new(self.txt, self.verbose))
-            .await?)
+        let txt = self.txt;
+        let response = client
+            .update_txt(TxtOptions::new(txt.clone(), self.verbose))
+            .await?;
+        if response.status() == Status::Ok {
+            state_file.modify(|state| state.record_txt(&domains, &txt));
+        }
+        Ok(response)
+    }
+
+    /// The request `run` would send, without sending it
+    pub fn plan(self) -> DryRun {
+        let state = StateFile::from(self.state).load();
+        let txt = self.txt;
+        let unchanged = self.account.domain.iter().all(|domain| {
+            state
+                .domain(domain)
+                .is_some_and(|state| state.txt.as_ref() == Some(&txt))
+        });
+        let expected = if unchanged {
+            Updated::Nochange
+        } else {
+            Updated::Updated
+        };
+        let request =
+            Client::from(self.account).dry_run_update_txt(TxtOptions::new(txt, self.verbose));
+        DryRun::new(request, None, Some(expected))
     }
 }
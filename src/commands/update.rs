@@ -2,14 +2,32 @@ use std::{
     error::Error as StdError,
     fmt,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
-    time::Duration,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use duck_dns::{Client, UpdateOptions};
-use log::{debug, error, info};
+use duck_dns::{Client, Label, Status, Token, UpdateOptions, Updated};
+use futures_util::future::join_all;
+use log::{debug, error, info, log, warn, Level};
+use public_ip::{DnsServer, Family, Service};
 use structopt::StructOpt;
 
-use crate::{check_ip_opts::CheckIpOpts, opts::Account, parse_duration::parse_duration};
+use crate::{
+    check_ip_opts::{IpLookup, PreflightOpts},
+    config::Config,
+    control::{sleep, Control, Cycle, Wake},
+    debounce::Debounce,
+    dry_run::DryRun,
+    hooks::{HookOpts, Hooks},
+    metrics::{GroupMetrics, Metrics, MetricsOpts},
+    opts::StateOpts,
+    parse_duration::parse_duration,
+    policy::{Cidr, Policy},
+    reconcile::Reconcile,
+    state::{State, StateFile},
+};
 
 #[derive(Debug)]
 struct IpOptError();
@@ -22,7 +40,73 @@ impl fmt::Display for IpOptError {
 
 impl StdError for IpOptError {}
 
-#[derive(StructOpt, Debug)]
+#[derive(Debug)]
+pub struct NoConfigError();
+
+impl fmt::Display for NoConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no --config file given")
+    }
+}
+
+impl StdError for NoConfigError {}
+
+#[derive(Debug)]
+struct GroupsFailedError {
+    failed: usize,
+    total: usize,
+}
+
+impl fmt::Display for GroupsFailedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} group updates failed", self.failed, self.total)
+    }
+}
+
+impl StdError for GroupsFailedError {}
+
+#[derive(Debug)]
+pub struct ParseUpdateFamilyError();
+
+impl fmt::Display for ParseUpdateFamilyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "family must be one of ipv4, ipv6, or both")
+    }
+}
+
+impl StdError for ParseUpdateFamilyError {}
+
+#[derive(Copy, Clone, Debug)]
+pub enum UpdateFamily {
+    V4,
+    V6,
+    Both,
+}
+
+impl UpdateFamily {
+    fn families(self) -> &'static [Family] {
+        match self {
+            UpdateFamily::V4 => &[Family::V4],
+            UpdateFamily::V6 => &[Family::V6],
+            UpdateFamily::Both => &[Family::V4, Family::V6],
+        }
+    }
+}
+
+impl FromStr for UpdateFamily {
+    type Err = ParseUpdateFamilyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "4" | "ipv4" | "v4" => Ok(UpdateFamily::V4),
+            "6" | "ipv6" | "v6" => Ok(UpdateFamily::V6),
+            "both" => Ok(UpdateFamily::Both),
+            _ => Err(ParseUpdateFamilyError()),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Default)]
 pub struct Update {
     #[structopt(short, long)]
     pub ip: Option<IpAddr>,
@@ -30,101 +114,628 @@ pub struct Update {
     pub ipv6: Option<Ipv6Addr>,
     #[structopt(short, long, conflicts_with_all = &["ip", "ipv6"])]
     pub preflight_ip: bool,
+    /// IP source to check before updating, a preset name, URL, 'json:<path> <url>', '@server [type] name', etc.
     #[structopt(short = "o", long, conflicts_with_all = &["ip", "ipv6"])]
-    pub preflight_opts: Option<CheckIpOpts>,
+    pub preflight_opts: Option<Service>,
+    #[structopt(flatten)]
+    pub preflight: PreflightOpts,
+    /// Only publish a detected address within this range, implies --preflight-ip
+    #[structopt(long, number_of_values = 1, conflicts_with_all = &["ip", "ipv6", "family"])]
+    pub allow_cidr: Vec<Cidr>,
+    /// Never publish a detected address within this range, implies --preflight-ip
+    #[structopt(long, number_of_values = 1, conflicts_with_all = &["ip", "ipv6", "family"])]
+    pub deny_cidr: Vec<Cidr>,
     #[structopt(short, long, parse(try_from_str = parse_duration), conflicts_with_all = &["ip", "ipv6"])]
     pub schedule: Option<Duration>,
+    /// Only update once a new address has been seen this many times in a row, implies --preflight-ip
+    #[structopt(long, requires = "schedule", conflicts_with_all = &["ip", "ipv6", "family"])]
+    pub confirmations: Option<u32>,
+    /// Only update once a new address has been seen for this long, implies --preflight-ip
+    #[structopt(long, parse(try_from_str = parse_duration), requires = "schedule", conflicts_with_all = &["ip", "ipv6", "family"])]
+    pub stable_for: Option<Duration>,
+    /// Send at most this many updates an hour, implies --preflight-ip
+    #[structopt(long, requires = "schedule", conflicts_with_all = &["ip", "ipv6", "family"])]
+    pub max_updates_per_hour: Option<u32>,
+    /// Each cycle compare with the address DuckDNS is serving, rather than the
+    /// last update, to correct changes made elsewhere, implies --preflight-ip
+    #[structopt(long, requires = "schedule", conflicts_with_all = &["ip", "ipv6", "family"])]
+    pub reconcile: bool,
+    /// Nameserver to check the served address with when reconciling, defaults to ns1.duckdns.org
+    #[structopt(long)]
+    pub reconcile_server: Option<DnsServer>,
+    /// Update at least this often even if the address hasn't changed, implies --preflight-ip
+    #[structopt(long, parse(try_from_str = parse_duration), requires = "schedule", conflicts_with_all = &["ip", "ipv6", "family"])]
+    pub refresh: Option<Duration>,
+    /// Send the update over ipv4, ipv6, or both (as two requests), letting
+    /// DuckDNS detect the address from the connection
+    #[structopt(
+        short,
+        long,
+        conflicts_with_all = &["ip", "ipv6", "preflight-ip", "preflight-opts"],
+    )]
+    pub family: Option<UpdateFamily>,
+    /// Update every group in the --config file, concurrently, rather than the given domains
+    #[structopt(
+        long,
+        conflicts_with_all = &["ip", "ipv6", "preflight-ip", "preflight-opts", "schedule", "family"],
+    )]
+    pub all: bool,
+    /// Do everything but send the update, printing the request that would be
+    /// sent and whether it's expected to change anything, with --schedule
+    /// only the first cycle
+    #[structopt(long)]
+    pub dry_run: bool,
+    #[structopt(
+        short,
+        long,
+        parse(from_str = shared_token),
+        env = "DUCKDNS_TOKEN",
+        required_unless = "all"
+    )]
+    pub token: Option<Arc<Token>>,
+    #[structopt(required_unless = "all", conflicts_with = "all")]
+    pub domain: Vec<Label>,
+    #[structopt(flatten)]
+    pub state: StateOpts,
+    #[structopt(flatten)]
+    pub metrics: MetricsOpts,
     #[structopt(flatten)]
-    pub account: Account,
+    pub hooks: HookOpts,
+    /// Where a supervisor wants the scheduled update's metrics recorded
+    #[structopt(skip)]
+    pub group_metrics: GroupMetrics,
+    #[structopt(skip)]
+    pub config: Option<PathBuf>,
     #[structopt(skip)]
     pub verbose: bool,
 }
 
 impl Update {
-    pub async fn run(self) -> Result<duck_dns::Response, Box<dyn StdError>> {
+    fn policy(&self) -> Policy {
+        Policy::new(self.allow_cidr.clone(), self.deny_cidr.clone())
+    }
+
+    fn debounce(&self) -> Debounce {
+        Debounce::new(
+            self.confirmations.unwrap_or(1),
+            self.stable_for.unwrap_or_default(),
+            self.max_updates_per_hour,
+        )
+    }
+
+    // a policy or debounce can only be applied to an address we've looked up
+    fn preflight(&self) -> bool {
+        self.preflight_ip
+            || self.preflight_opts.is_some()
+            || !self.policy().is_empty()
+            || self.confirmations.is_some()
+            || self.stable_for.is_some()
+            || self.max_updates_per_hour.is_some()
+            || self.reconcile
+            || self.refresh.is_some()
+    }
+
+    // without --all clap ensures there's a token and domains
+    fn client(token: Option<Arc<Token>>, domains: Vec<Label>) -> Client {
+        Client::from_shared(domains, token.expect("token is required without --all"))
+    }
+
+    pub async fn run(self) -> Result<Vec<duck_dns::Response>, Box<dyn StdError>> {
+        self.run_with(None).await
+    }
+
+    /// The requests `run` would send straight away, without sending them
+    pub async fn plan(self) -> Result<Vec<DryRun>, Box<dyn StdError>> {
+        if self.all {
+            return plan_all(self).await;
+        }
+        plan_now(self).await
+    }
+
+    /// Run, with a scheduled update woken, stopped, and followed through
+    /// `control`
+    pub async fn run_with(
+        self,
+        mut control: Option<Control>,
+    ) -> Result<Vec<duck_dns::Response>, Box<dyn StdError>> {
+        if self.all {
+            return update_all(self).await;
+        }
         let schedule = match self.schedule {
             Some(schedule) => schedule,
             None => return update_now(self).await,
         };
 
-        let client = Client::from(self.account);
-        let service = match self.preflight_opts {
-            Some(opts) => Some(opts.into_service().await?),
-            None if self.preflight_ip => Some(Default::default()),
-            None => None,
+        let metrics = if self.metrics.is_enabled() {
+            let name = self
+                .domain
+                .iter()
+                .map(|label| label.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            Metrics::start(&self.metrics)
+                .await?
+                .group(&name, &self.domain)
+        } else {
+            self.group_metrics.clone()
+        };
+        let policy = self.policy();
+        let mut debounce = self.debounce();
+        let preflight = self.preflight();
+        let state_file = StateFile::from(self.state);
+        let state = state_file.load();
+        let domains = self.domain.clone();
+        let mut hooks = Hooks::new(self.hooks, &domains)?;
+        metrics.restore(&state);
+        let reconcile = if self.reconcile {
+            Some(Reconcile::new(self.reconcile_server, &domains)?)
+        } else {
+            None
+        };
+        let client = Self::client(self.token, self.domain);
+        let lookup = if preflight {
+            Some(IpLookup::new(self.preflight_opts, self.preflight.into()))
+        } else {
+            None
         };
 
-        let mut prev_ip = Ipv4Addr::UNSPECIFIED;
+        // resume from the last published address, rather than forcing an
+        // update on every restart
+        let mut prev_ip = lookup
+            .as_ref()
+            .and_then(|lookup| state.ip(&domains, lookup.family()))
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let mut last_published = state.updated(&domains).unwrap_or(UNIX_EPOCH);
+        let mut woken = false;
+        // whether we've already published while DuckDNS wasn't serving an
+        // address, so a record that's slow to appear isn't updated every cycle
+        let mut published_unserved = false;
 
         loop {
-            if let Some(ref service) = service {
-                match update_preflight_schedule(&client, service, prev_ip, self.verbose).await {
+            if let Some(ref control) = control {
+                control.started();
+            }
+            let cycle;
+            if let Some(ref lookup) = lookup {
+                let served = match reconcile {
+                    Some(ref reconcile) => served_ip(reconcile, lookup.family(), prev_ip).await,
+                    None => Some(prev_ip),
+                };
+                let current_ip = match served {
+                    Some(ip) => {
+                        published_unserved = false;
+                        ip
+                    }
+                    None if published_unserved => prev_ip,
+                    None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                };
+                let force = woken
+                    || self.refresh.is_some_and(|refresh| {
+                        last_published
+                            .elapsed()
+                            .map_or(true, |elapsed| elapsed >= refresh)
+                    });
+                let detected = check_ip(lookup, &metrics).await;
+                // DuckDNS already serves what we'd publish, whatever we last
+                // sent, so that's the address to move on from
+                if let (Some(served), Ok(ip)) = (served, &detected) {
+                    if served == *ip {
+                        prev_ip = served;
+                    }
+                }
+                let result = match detected {
+                    Ok(ip) => update_preflight_schedule(
+                        &client,
+                        ip,
+                        &policy,
+                        &mut debounce,
+                        current_ip,
+                        force,
+                        self.verbose,
+                    )
+                    .await
+                    .inspect_err(|_| metrics.updated(None)),
+                    Err(e) => Err(e.into()),
+                };
+                match result {
                     Ok((res, ip)) => {
                         debug!("prev_ip = {}, ip = {}", prev_ip, ip);
-                        prev_ip = ip;
-                        match res {
-                            Some(r) => info!("{}", r),
-                            None => info!("no ip change, skipping update"),
-                        }
+                        cycle = match res {
+                            Some(r) => {
+                                info!("{}", r);
+                                metrics.updated(Some(&r));
+                                // only move on from the previous address once
+                                // it's published, so a failed update is retried
+                                if r.status() == Status::Ok {
+                                    prev_ip = ip;
+                                    published_unserved = served.is_none();
+                                    last_published = SystemTime::now();
+                                    record(&state_file, &domains, &[ip], &r);
+                                    metrics.published(&[ip], &r);
+                                    let old = Some(current_ip).filter(|ip| !ip.is_unspecified());
+                                    hooks.changed(old, ip, &r).await;
+                                    Cycle::Updated(Some(ip))
+                                } else {
+                                    let e = format!("DuckDNS responded {}", r.status());
+                                    hooks.failed(Some(&r), &e).await;
+                                    Cycle::Failed(e)
+                                }
+                            }
+                            None => Cycle::Unchanged(ip),
+                        };
                     }
                     Err(e) => {
                         error!("error during IP preflight: {}", e);
-                        let d = schedule.min(Duration::from_secs(60));
-                        debug!("sleeping for {:?}", d);
-                        tokio::time::sleep(d).await;
+                        hooks.failed(None, &e.to_string()).await;
+                        let cycle = Cycle::Failed(e.to_string());
+                        metrics.finished(&cycle);
+                        if let Some(ref control) = control {
+                            control.finished(cycle);
+                        }
+                        metrics.retried();
+                        let retry = schedule.min(Duration::from_secs(60));
+                        metrics.sleeping(retry);
+                        match sleep(retry, &mut control).await {
+                            Some(Wake::Stop) => {
+                                hooks.flush().await;
+                                return Ok(Vec::new());
+                            }
+                            woke => woken = woke.is_some(),
+                        }
                         continue;
                     }
                 };
             } else {
-                match update_schedule(&client, self.verbose).await {
-                    Ok(r) => info!("{}", r),
-                    Err(e) => error!("{}", e),
-                };
+                let mut updated = Cycle::Updated(None);
+                let mut failed = None;
+                for (_, client) in clients(&client, self.family) {
+                    let result = update_schedule(&client, self.verbose).await;
+                    metrics.updated(result.as_ref().ok());
+                    match result {
+                        Ok(r) => {
+                            let old = state_file.load();
+                            record(&state_file, &domains, &[], &r);
+                            if r.status() == Status::Ok {
+                                metrics.published(&[], &r);
+                                changed(&mut hooks, &old, &domains, &[], &r).await;
+                            }
+                            info!("{}", r);
+                            if r.status() != Status::Ok {
+                                let e = format!("DuckDNS responded {}", r.status());
+                                hooks.failed(Some(&r), &e).await;
+                                failed = Some(e);
+                            } else if let Some(ip) = r.ipv4() {
+                                updated = Cycle::Updated(Some(IpAddr::V4(*ip)));
+                            } else if let Some(ip) = r.ipv6() {
+                                updated = Cycle::Updated(Some(IpAddr::V6(*ip)));
+                            }
+                        }
+                        Err(e) => {
+                            error!("{}", e);
+                            hooks.failed(None, &e.to_string()).await;
+                            failed = Some(e.to_string());
+                        }
+                    };
+                }
+                cycle = failed.map_or(updated, Cycle::Failed);
+            }
+            if !cycle.is_failed() {
+                hooks.succeeded();
+            }
+            metrics.finished(&cycle);
+            if let Some(ref control) = control {
+                control.finished(cycle);
             }
 
-            debug!("sleeping for {:?}", schedule);
-            tokio::time::sleep(schedule).await
+            metrics.sleeping(schedule);
+            match sleep(schedule, &mut control).await {
+                Some(Wake::Stop) => {
+                    hooks.flush().await;
+                    return Ok(Vec::new());
+                }
+                woke => woken = woke.is_some(),
+            }
         }
     }
 }
 
-async fn update_now(opts: Update) -> Result<duck_dns::Response, Box<dyn StdError>> {
-    let client = Client::from(opts.account);
+// shared, as a config file's groups for the same account are
+fn shared_token(s: &str) -> Arc<Token> {
+    Arc::new(Token::from(s))
+}
+
+async fn update_all(opts: Update) -> Result<Vec<duck_dns::Response>, Box<dyn StdError>> {
+    let path = opts.config.ok_or(NoConfigError())?;
+    let mut updates = Config::load(&path)?.updates(&opts.state, &opts.hooks, opts.verbose)?;
+    if opts.metrics.is_enabled() {
+        let metrics = Metrics::start(&opts.metrics).await?;
+        for (name, update) in &mut updates {
+            update.group_metrics = metrics.group(name, &update.domain);
+        }
+    }
+    let total = updates.len();
+    // the updates share a thread, so one group's scheduled loop doesn't hold
+    // up the others, and the state file isn't written to concurrently
+    let results = join_all(
+        updates
+            .into_iter()
+            .map(|(name, update)| async move { (name, update.run().await) }),
+    )
+    .await;
+    let mut responses = Vec::new();
+    let mut failed = 0;
+    for (name, result) in results {
+        match result {
+            Ok(r) => responses.extend(r),
+            Err(e) => {
+                error!("group {}: {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(GroupsFailedError { failed, total }.into());
+    }
+    Ok(responses)
+}
+
+// a client for each family the update is sent over, from the unspecified
+// address so DuckDNS sees our address of that family
+fn clients(client: &Client, family: Option<UpdateFamily>) -> Vec<(Option<Family>, Client)> {
+    match family {
+        Some(family) => family
+            .families()
+            .iter()
+            .map(|f| (Some(*f), client.clone().local_address(f.unspecified())))
+            .collect(),
+        None => vec![(None, client.clone())],
+    }
+}
 
-    let args = match (opts.ip, opts.ipv6) {
-        (Some(IpAddr::V4(ip)), None) => UpdateOptions::ipv4(ip, opts.verbose),
+async fn plan_all(opts: Update) -> Result<Vec<DryRun>, Box<dyn StdError>> {
+    let path = opts.config.ok_or(NoConfigError())?;
+    let updates = Config::load(&path)?.updates(&opts.state, &opts.hooks, opts.verbose)?;
+    let total = updates.len();
+    let mut planned = Vec::new();
+    let mut failed = 0;
+    for (name, update) in updates {
+        match plan_now(update).await {
+            Ok(p) => planned.extend(p),
+            Err(e) => {
+                error!("group {}: {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(GroupsFailedError { failed, total }.into());
+    }
+    Ok(planned)
+}
+
+async fn plan_now(opts: Update) -> Result<Vec<DryRun>, Box<dyn StdError>> {
+    let state = StateFile::from(opts.state.clone()).load();
+    // a dry run shouldn't set anything off, so has no hooks
+    let (args, ips) = match arguments(&opts, &state, None).await? {
+        Arguments::Send(args, ips) => (args, ips),
+        Arguments::Skip(_, reason) => return Ok(vec![DryRun::skipped(&opts.domain, reason)]),
+    };
+    // only known when we're sending the addresses
+    let expected = if ips.is_empty() {
+        None
+    } else if ips
+        .iter()
+        .all(|ip| state.ip(&opts.domain, Family::of(ip)) == Some(*ip))
+    {
+        Some(Updated::Nochange)
+    } else {
+        Some(Updated::Updated)
+    };
+    let client = Update::client(opts.token, opts.domain);
+    Ok(clients(&client, opts.family)
+        .into_iter()
+        .map(|(family, client)| DryRun::new(client.dry_run_update(args), family, expected))
+        .collect())
+}
+
+// what an update would send, or why it should be skipped
+enum Arguments {
+    // the options to update with, and the addresses they publish
+    Send(UpdateOptions, Vec<IpAddr>),
+    // logged at the same level as the scheduled update's skips
+    Skip(Level, String),
+}
+
+async fn arguments(
+    opts: &Update,
+    state: &State,
+    hooks: Option<&mut Hooks>,
+) -> Result<Arguments, Box<dyn StdError>> {
+    let (args, ips) = match (opts.ip, opts.ipv6) {
+        (Some(IpAddr::V4(ip)), None) => (UpdateOptions::ipv4(ip, opts.verbose), vec![ip.into()]),
         (Some(IpAddr::V6(ipv6)), None) | (None, Some(ipv6)) => {
-            UpdateOptions::ipv6(ipv6, opts.verbose)
+            (UpdateOptions::ipv6(ipv6, opts.verbose), vec![ipv6.into()])
         }
-        (Some(IpAddr::V4(ip)), Some(ipv6)) => UpdateOptions::new(ip, ipv6, opts.verbose),
+        (Some(IpAddr::V4(ip)), Some(ipv6)) => (
+            UpdateOptions::new(ip, ipv6, opts.verbose),
+            vec![ip.into(), ipv6.into()],
+        ),
         (Some(IpAddr::V6(_)), Some(_)) => return Err(IpOptError().into()),
-        (None, None) if opts.preflight_ip || opts.preflight_opts.is_some() => {
-            let service = match opts.preflight_opts {
-                Some(opts) => opts.into_service().await?,
-                None => Default::default(),
+        (None, None) if opts.preflight() => {
+            let lookup = IpLookup::new(opts.preflight_opts.clone(), opts.preflight.clone().into());
+            let ip = match lookup.ip().await {
+                Ok(ip) => ip,
+                Err(e) => {
+                    if let Some(hooks) = hooks {
+                        hooks.failed(None, &e.to_string()).await;
+                        hooks.flush().await;
+                    }
+                    return Err(e.into());
+                }
             };
-            let ip = service.ipv4().await?;
-            UpdateOptions::ipv4(ip, opts.verbose)
+            if let Err(e) = opts.policy().check(&ip) {
+                return Ok(Arguments::Skip(Level::Warn, e.to_string()));
+            }
+            if state.ip(&opts.domain, Family::of(&ip)) == Some(ip) {
+                let reason = format!("{} unchanged since last update", ip);
+                return Ok(Arguments::Skip(Level::Info, reason));
+            }
+            (ip_options(ip, opts.verbose), vec![ip])
         }
-        (None, None) if opts.verbose => UpdateOptions::verbose(),
-        (None, None) => UpdateOptions::default(),
+        (None, None) if opts.verbose => (UpdateOptions::verbose(), Vec::new()),
+        (None, None) => (UpdateOptions::default(), Vec::new()),
     };
+    Ok(Arguments::Send(args, ips))
+}
 
-    Ok(client.update(args).await?)
+async fn update_now(opts: Update) -> Result<Vec<duck_dns::Response>, Box<dyn StdError>> {
+    let state_file = StateFile::from(opts.state.clone());
+    let state = state_file.load();
+    let domains = opts.domain.clone();
+    let mut hooks = Hooks::new(opts.hooks.clone(), &domains)?;
+    let (args, ips) = match arguments(&opts, &state, Some(&mut hooks)).await? {
+        Arguments::Send(args, ips) => (args, ips),
+        Arguments::Skip(level, reason) => {
+            log!(level, "skipping update: {}", reason);
+            return Ok(Vec::new());
+        }
+    };
+    let client = Update::client(opts.token, opts.domain);
+
+    let mut responses = Vec::new();
+    for (_, client) in clients(&client, opts.family) {
+        let response = match client.update(args).await {
+            Ok(response) => response,
+            Err(e) => {
+                hooks.failed(None, &e.to_string()).await;
+                hooks.flush().await;
+                return Err(e.into());
+            }
+        };
+        record(&state_file, &domains, &ips, &response);
+        if response.status() == Status::Ok {
+            changed(&mut hooks, &state, &domains, &ips, &response).await;
+        } else {
+            let e = format!("DuckDNS responded {}", response.status());
+            hooks.failed(Some(&response), &e).await;
+        }
+        responses.push(response);
+    }
+    // a one-shot update exits straight after, so let notifications finish
+    hooks.flush().await;
+    Ok(responses)
+}
+
+// record what a successful update published, the addresses we sent along
+// with any DuckDNS reports back
+fn record(
+    state_file: &StateFile,
+    domains: &[Label],
+    ips: &[IpAddr],
+    response: &duck_dns::Response,
+) {
+    if response.status() == Status::Ok {
+        state_file.modify(|state| {
+            for ip in ips {
+                state.record_ip(domains, *ip);
+            }
+            state.record_response(domains, response);
+        });
+    }
+}
+
+// run the change hook for the addresses a successful update published, the
+// ones we sent, or failing that any DuckDNS reports
+async fn changed(
+    hooks: &mut Hooks,
+    old: &State,
+    domains: &[Label],
+    ips: &[IpAddr],
+    response: &duck_dns::Response,
+) {
+    let reported = response
+        .ipv4()
+        .map(|ip| IpAddr::V4(*ip))
+        .into_iter()
+        .chain(response.ipv6().map(|ip| IpAddr::V6(*ip)))
+        .collect::<Vec<_>>();
+    let ips = if ips.is_empty() { &reported } else { ips };
+    for ip in ips {
+        hooks
+            .changed(old.ip(domains, Family::of(ip)), *ip, response)
+            .await;
+    }
+}
+
+fn ip_options(ip: IpAddr, verbose: bool) -> UpdateOptions {
+    match ip {
+        IpAddr::V4(ip) => UpdateOptions::ipv4(ip, verbose),
+        IpAddr::V6(ip) => UpdateOptions::ipv6(ip, verbose),
+    }
+}
+
+// the address DuckDNS is serving, or `None` if it isn't serving one, falling
+// back to the last one we published if that can't be checked
+async fn served_ip(reconcile: &Reconcile, family: Family, prev_ip: IpAddr) -> Option<IpAddr> {
+    match reconcile.published(family).await {
+        Ok(Some(ip)) => {
+            if ip != prev_ip {
+                info!("DuckDNS is serving {}, last updated to {}", ip, prev_ip);
+            }
+            Some(ip)
+        }
+        Ok(None) => {
+            info!("DuckDNS isn't serving a consistent {} address", family);
+            None
+        }
+        Err(e) => {
+            warn!("couldn't check served address, using last update: {}", e);
+            Some(prev_ip)
+        }
+    }
+}
+
+// look up our address, recording how long it took and whether it worked
+async fn check_ip(lookup: &IpLookup, metrics: &GroupMetrics) -> Result<IpAddr, public_ip::Error> {
+    let start = Instant::now();
+    let ip = lookup.ip().await;
+    metrics.checked(
+        &lookup.source(),
+        start.elapsed(),
+        ip.as_ref().copied().map_err(ToString::to_string),
+    );
+    ip
 }
 
 async fn update_preflight_schedule(
     client: &Client,
-    service: &public_ip::Service,
-    prev_ip: Ipv4Addr,
+    ip: IpAddr,
+    policy: &Policy,
+    debounce: &mut Debounce,
+    prev_ip: IpAddr,
+    force: bool,
     verbose: bool,
-) -> Result<(Option<duck_dns::Response>, Ipv4Addr), Box<dyn StdError>> {
-    let ip = service.ipv4().await?;
-    if ip == prev_ip {
+) -> Result<(Option<duck_dns::Response>, IpAddr), Box<dyn StdError>> {
+    let unchanged = ip == prev_ip;
+    if unchanged && !force {
+        debounce.reset();
+        info!("no ip change, skipping update");
+        return Ok((None, prev_ip));
+    }
+    if let Err(e) = policy.check(&ip) {
+        warn!("skipping update: {}", e);
+        return Ok((None, prev_ip));
+    }
+    if unchanged {
+        debounce.reset();
+        info!("refreshing unchanged ip {}", ip);
+    } else if let Err(reason) = debounce.observe(ip, Instant::now()) {
+        info!("not updating to {} yet, {}", ip, reason);
         return Ok((None, prev_ip));
     }
-    let args = UpdateOptions::ipv4(ip, verbose);
+    let args = ip_options(ip, verbose);
     let response = client.update(args).await?;
+    debounce.published(Instant::now());
     Ok((Some(response), ip))
 }
 
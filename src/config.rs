@@ -0,0 +1,401 @@
+use std::{
+    collections::BTreeMap,
+    error::Error as StdError,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use duck_dns::{Label, Token};
+use public_ip::{DnsServer, Family, Service};
+use serde::{de, Deserialize, Deserializer};
+use url::Url;
+use zeroize::Zeroizing;
+
+use crate::{
+    check_ip_opts::SourceOpts,
+    commands::update::{Update, UpdateFamily},
+    hooks::HookOpts,
+    opts::StateOpts,
+    parse_duration::parse_duration,
+    policy::Cidr,
+    webhook::{Format, WebhookOpts},
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    ReadToken(String, PathBuf, io::Error),
+    MissingToken(String),
+    UnknownAccount(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "couldn't read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::ReadToken(account, path, e) => write!(
+                f,
+                "couldn't read token file {} for account {}: {}",
+                path.display(),
+                account,
+                e
+            ),
+            ConfigError::MissingToken(account) => {
+                write!(f, "account {} needs one of token or token_file", account)
+            }
+            ConfigError::UnknownAccount(group, account) => {
+                write!(f, "group {} uses undefined account {}", group, account)
+            }
+        }
+    }
+}
+
+impl StdError for ConfigError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            ConfigError::Read(_, e) | ConfigError::ReadToken(_, _, e) => Some(e),
+            ConfigError::Parse(_, e) => Some(e),
+            ConfigError::MissingToken(_) | ConfigError::UnknownAccount(_, _) => None,
+        }
+    }
+}
+
+/// The `--config` file, e.g.
+///
+/// ```toml
+/// state_file = "/var/lib/quack/state.json"
+///
+/// [accounts.personal]
+/// token_file = "/etc/quack/token"
+///
+/// [groups.home]
+/// account = "personal"
+/// domains = ["myhome", "mynas"]
+/// source = "opendns"
+/// family = "both"
+/// schedule = "5m"
+/// deny_cidr = ["100.64.0.0/10"]
+/// on_change = "systemctl reload nginx"
+///
+/// [groups.nas]
+/// account = "personal"
+/// domains = ["mynas"]
+/// source = "json:ip https://router.lan/api/wan"
+///
+/// [groups.nas.source_options]
+/// method = "POST"
+/// headers = ["Accept: application/json"]
+/// user = "admin:hunter2"
+/// insecure = true
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub state_file: Option<PathBuf>,
+    #[serde(default)]
+    accounts: BTreeMap<String, AccountConfig>,
+    #[serde(default)]
+    pub groups: BTreeMap<String, Group>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccountConfig {
+    #[serde(default, deserialize_with = "shared_token")]
+    token: Option<Arc<Token>>,
+    token_file: Option<PathBuf>,
+}
+
+/// A set of domains updated together, with their own IP source and schedule
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    pub account: String,
+    #[serde(deserialize_with = "vec_from_str")]
+    pub domains: Vec<Label>,
+    /// IP source spec, without one DuckDNS detects the address
+    #[serde(default, deserialize_with = "option_from_str")]
+    pub source: Option<Service>,
+    /// How to reach the IP source, its HTTP method, headers, auth, etc.
+    #[serde(default)]
+    pub source_options: SourceOpts,
+    #[serde(default, deserialize_with = "option_from_str")]
+    pub family: Option<UpdateFamily>,
+    #[serde(default, deserialize_with = "option_duration")]
+    pub schedule: Option<Duration>,
+    #[serde(default)]
+    pub allow_non_global: bool,
+    #[serde(default, deserialize_with = "vec_from_str")]
+    pub allow_cidr: Vec<Cidr>,
+    #[serde(default, deserialize_with = "vec_from_str")]
+    pub deny_cidr: Vec<Cidr>,
+    pub confirmations: Option<u32>,
+    #[serde(default, deserialize_with = "option_duration")]
+    pub stable_for: Option<Duration>,
+    pub max_updates_per_hour: Option<u32>,
+    #[serde(default)]
+    pub reconcile: bool,
+    #[serde(default, deserialize_with = "option_from_str")]
+    pub reconcile_server: Option<DnsServer>,
+    #[serde(default, deserialize_with = "option_duration")]
+    pub refresh: Option<Duration>,
+    pub on_change: Option<String>,
+    pub on_error: Option<String>,
+    #[serde(default, deserialize_with = "option_duration")]
+    pub hook_timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "option_from_str")]
+    pub webhook: Option<Url>,
+    #[serde(default, deserialize_with = "option_from_str")]
+    pub webhook_format: Option<Format>,
+    pub webhook_template: Option<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        // the file may hold tokens, so its contents are zeroed once parsed
+        let contents = Zeroizing::new(
+            fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_owned(), e))?,
+        );
+        let config: Config =
+            toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_owned(), e))?;
+        for (name, group) in &config.groups {
+            if !config.accounts.contains_key(&group.account) {
+                return Err(ConfigError::UnknownAccount(
+                    name.clone(),
+                    group.account.clone(),
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    fn token(&self, account: &str) -> Result<Arc<Token>, ConfigError> {
+        let config = &self.accounts[account];
+        match (&config.token, &config.token_file) {
+            (Some(token), _) => Ok(token.clone()),
+            // the file's contents, untrimmed, are zeroed too
+            (None, Some(path)) => fs::read_to_string(path)
+                .map(|contents| Arc::new(Token::from(Zeroizing::new(contents).trim())))
+                .map_err(|e| ConfigError::ReadToken(account.to_owned(), path.clone(), e)),
+            (None, None) => Err(ConfigError::MissingToken(account.to_owned())),
+        }
+    }
+
+    /// The updates for every group, named by group. A group with an IP
+    /// source and both families is looked up and updated once per family.
+    /// Hooks a group doesn't set fall back to `hooks`.
+    pub fn updates(
+        &self,
+        state: &StateOpts,
+        hooks: &HookOpts,
+        verbose: bool,
+    ) -> Result<Vec<(String, Update)>, ConfigError> {
+        let mut updates = Vec::new();
+        for (name, group) in &self.groups {
+            let token = self.token(&group.account)?;
+            let families = match (&group.source, group.family) {
+                (Some(_), Some(UpdateFamily::Both)) => vec![Some(Family::V4), Some(Family::V6)],
+                (Some(_), Some(UpdateFamily::V6)) => vec![Some(Family::V6)],
+                (Some(_), _) => vec![Some(Family::V4)],
+                (None, _) => vec![None],
+            };
+            let split = families.len() > 1;
+            for preflight_family in families {
+                // each family is updated separately, so needs its own name
+                let name = match preflight_family {
+                    Some(family) if split => format!("{} {}", name, family),
+                    _ => name.clone(),
+                };
+                updates.push((
+                    name,
+                    Update {
+                        token: Some(token.clone()),
+                        domain: group.domains.clone(),
+                        preflight_opts: group.source.clone(),
+                        preflight: SourceOpts {
+                            family: preflight_family,
+                            allow_non_global: group.allow_non_global,
+                            ..group.source_options.clone()
+                        }
+                        .into(),
+                        // without a source DuckDNS detects the address of
+                        // the family the request is sent over
+                        family: if group.source.is_none() {
+                            group.family
+                        } else {
+                            None
+                        },
+                        schedule: group.schedule,
+                        allow_cidr: group.allow_cidr.clone(),
+                        deny_cidr: group.deny_cidr.clone(),
+                        confirmations: group.confirmations,
+                        stable_for: group.stable_for,
+                        max_updates_per_hour: group.max_updates_per_hour,
+                        reconcile: group.reconcile,
+                        reconcile_server: group.reconcile_server.clone(),
+                        refresh: group.refresh,
+                        state: StateOpts {
+                            state_file: state
+                                .state_file
+                                .clone()
+                                .or_else(|| self.state_file.clone()),
+                            no_state: state.no_state,
+                        },
+                        hooks: HookOpts {
+                            on_change: group.on_change.clone(),
+                            on_error: group.on_error.clone(),
+                            hook_timeout: group.hook_timeout,
+                            webhook: WebhookOpts {
+                                webhook: group.webhook.clone(),
+                                webhook_format: group.webhook_format,
+                                webhook_template: group.webhook_template.clone(),
+                                ..Default::default()
+                            },
+                        }
+                        .or(hooks),
+                        verbose,
+                        ..Default::default()
+                    },
+                ));
+            }
+        }
+        Ok(updates)
+    }
+}
+
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+pub(crate) fn option_from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    from_str(deserializer).map(Some)
+}
+
+pub(crate) fn vec_from_str<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .collect()
+}
+
+// a token, shared by every group using the account
+fn shared_token<'de, D>(deserializer: D) -> Result<Option<Arc<Token>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Token::deserialize(deserializer).map(|token| Some(Arc::new(token)))
+}
+
+fn option_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_duration(&s).map(Some).map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use public_ip::{Family, Method};
+
+    use super::Config;
+    use crate::{hooks::HookOpts, opts::StateOpts};
+
+    const CONFIG: &str = r#"
+        [accounts.a]
+        token = "secret"
+
+        [groups.dual]
+        account = "a"
+        domains = ["one", "two"]
+        source = "opendns"
+        family = "both"
+        schedule = "5m"
+        deny_cidr = ["100.64.0.0/10"]
+        on_change = "reload-firewall"
+
+        [groups.dual.source_options]
+        method = "POST"
+        headers = ["Accept: text/plain"]
+        insecure = true
+
+        [groups.plain]
+        account = "a"
+        domains = ["three"]
+    "#;
+
+    #[test]
+    fn it_expands_groups_into_updates() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let state = StateOpts {
+            state_file: None,
+            no_state: true,
+        };
+        let hooks = HookOpts {
+            on_change: Some("notify".to_owned()),
+            on_error: Some("alert".to_owned()),
+            hook_timeout: None,
+            webhook: Default::default(),
+        };
+        let updates = config.updates(&state, &hooks, false).unwrap();
+        let names = updates.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["dual ipv4", "dual ipv6", "plain"]);
+        let (_, dual) = &updates[1];
+        assert_eq!(dual.domain.len(), 2);
+        assert_eq!(dual.preflight.family, Some(Family::V6));
+        assert_eq!(dual.preflight.method, Some(Method::POST));
+        assert_eq!(dual.preflight.headers.len(), 1);
+        assert!(dual.preflight.insecure);
+        assert_eq!(dual.schedule, Some(Duration::from_secs(300)));
+        assert_eq!(dual.deny_cidr.len(), 1);
+        assert!(dual.family.is_none());
+        assert_eq!(dual.hooks.on_change.as_deref(), Some("reload-firewall"));
+        assert_eq!(dual.hooks.on_error.as_deref(), Some("alert"));
+        let (_, plain) = &updates[2];
+        assert_eq!(plain.hooks.on_change.as_deref(), Some("notify"));
+        assert!(plain.preflight_opts.is_none());
+        assert!(plain.schedule.is_none());
+    }
+
+    #[test]
+    fn it_reports_bad_values() {
+        let err = toml::from_str::<Config>(
+            r#"
+            [groups.g]
+            account = "a"
+            domains = ["one"]
+            schedule = "5 fortnights"
+        "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("unrecognized input"), "{}", err);
+        assert!(
+            toml::from_str::<Config>("[groups.g]\naccount = \"a\"\ndomains = []\ncolour = 1")
+                .is_err()
+        );
+    }
+}
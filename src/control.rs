@@ -0,0 +1,99 @@
+use std::{fmt, net::IpAddr, time::Duration};
+
+use log::debug;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc,
+};
+
+/// Interrupts a scheduled update's sleep
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Wake {
+    /// Check and update now, even if the address hasn't changed
+    Check,
+    /// Stop once any update in progress has finished
+    Stop,
+}
+
+/// How a scheduled update's cycle went
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Cycle {
+    /// An update was sent, with the address if known
+    Updated(Option<IpAddr>),
+    /// No update was needed, or it was held back
+    Unchanged(IpAddr),
+    Failed(String),
+}
+
+impl Cycle {
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Cycle::Failed(_))
+    }
+}
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cycle::Updated(Some(ip)) => write!(f, "updated to {}", ip),
+            Cycle::Updated(None) => write!(f, "updated"),
+            Cycle::Unchanged(ip) => write!(f, "{} unchanged", ip),
+            Cycle::Failed(e) => write!(f, "failed, {}", e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Started(String),
+    Finished(String, Cycle),
+}
+
+/// Lets a supervisor wake or stop a named scheduled update, and follow its
+/// progress.
+#[derive(Debug)]
+pub struct Control {
+    name: String,
+    wake: broadcast::Receiver<Wake>,
+    events: mpsc::UnboundedSender<Event>,
+}
+
+impl Control {
+    pub fn new(
+        name: String,
+        wake: broadcast::Receiver<Wake>,
+        events: mpsc::UnboundedSender<Event>,
+    ) -> Self {
+        Self { name, wake, events }
+    }
+
+    // the supervisor going away isn't our problem, so send errors are ignored
+
+    pub fn started(&self) {
+        let _ = self.events.send(Event::Started(self.name.clone()));
+    }
+
+    pub fn finished(&self, cycle: Cycle) {
+        let _ = self.events.send(Event::Finished(self.name.clone(), cycle));
+    }
+}
+
+/// Sleep until the next cycle, or until woken. A wake sent during an update
+/// is queued, so is acted on straight away.
+pub async fn sleep(duration: Duration, control: &mut Option<Control>) -> Option<Wake> {
+    debug!("sleeping for {:?}", duration);
+    let wake = match control {
+        Some(control) => &mut control.wake,
+        None => {
+            tokio::time::sleep(duration).await;
+            return None;
+        }
+    };
+    tokio::select! {
+        _ = tokio::time::sleep(duration) => None,
+        received = wake.recv() => match received {
+            Ok(wake) => Some(wake),
+            Err(RecvError::Lagged(_)) => Some(Wake::Check),
+            Err(RecvError::Closed) => Some(Wake::Stop),
+        },
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+/// Why a change of address isn't being published yet
+#[derive(Debug, Eq, PartialEq)]
+pub enum Suppressed {
+    Unconfirmed { seen: u32, needed: u32 },
+    Unstable { stable: Duration, needed: Duration },
+    RateLimited { limit: u32 },
+}
+
+impl fmt::Display for Suppressed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Suppressed::Unconfirmed { seen, needed } => {
+                write!(f, "seen {} of {} times needed", seen, needed)
+            }
+            Suppressed::Unstable { stable, needed } => write!(
+                f,
+                "stable for {}s of {}s needed",
+                stable.as_secs(),
+                needed.as_secs()
+            ),
+            Suppressed::RateLimited { limit } => {
+                write!(f, "limit of {} updates per hour reached", limit)
+            }
+        }
+    }
+}
+
+/// Holds back changes of address from a flapping or inconsistent source.
+#[derive(Debug, Default)]
+pub struct Debounce {
+    confirmations: u32,
+    stable_for: Duration,
+    max_per_hour: Option<u32>,
+    candidate: Option<(IpAddr, u32, Instant)>,
+    updates: VecDeque<Instant>,
+}
+
+impl Debounce {
+    pub fn new(confirmations: u32, stable_for: Duration, max_per_hour: Option<u32>) -> Self {
+        Self {
+            confirmations,
+            stable_for,
+            max_per_hour,
+            ..Default::default()
+        }
+    }
+
+    /// The address was seen unchanged, so forget any pending change
+    pub fn reset(&mut self) {
+        self.candidate = None;
+    }
+
+    /// Record that a new address was seen, `Ok` if it should be published
+    pub fn observe(&mut self, ip: IpAddr, now: Instant) -> Result<(), Suppressed> {
+        let (seen, since) = match self.candidate {
+            Some((candidate, seen, since)) if candidate == ip => (seen + 1, since),
+            _ => (1, now),
+        };
+        self.candidate = Some((ip, seen, since));
+
+        if seen < self.confirmations {
+            return Err(Suppressed::Unconfirmed {
+                seen,
+                needed: self.confirmations,
+            });
+        }
+        let stable = now.duration_since(since);
+        if stable < self.stable_for {
+            return Err(Suppressed::Unstable {
+                stable,
+                needed: self.stable_for,
+            });
+        }
+        while matches!(self.updates.front(), Some(t) if now.duration_since(*t) >= HOUR) {
+            self.updates.pop_front();
+        }
+        match self.max_per_hour {
+            Some(limit) if self.updates.len() >= limit as usize => {
+                Err(Suppressed::RateLimited { limit })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record that an update was sent
+    pub fn published(&mut self, now: Instant) {
+        self.candidate = None;
+        self.updates.push_back(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Debounce, Suppressed};
+
+    #[test]
+    fn it_requires_consecutive_observations() {
+        let now = Instant::now();
+        let (a, b) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        let mut debounce = Debounce::new(3, Duration::from_secs(0), None);
+        assert!(debounce.observe(a, now).is_err());
+        assert!(debounce.observe(a, now).is_err());
+        // flapping to another address starts the count again
+        assert_eq!(
+            debounce.observe(b, now),
+            Err(Suppressed::Unconfirmed { seen: 1, needed: 3 })
+        );
+        assert!(debounce.observe(b, now).is_err());
+        assert_eq!(debounce.observe(b, now), Ok(()));
+    }
+
+    #[test]
+    fn it_requires_stable_time() {
+        let now = Instant::now();
+        let ip = "192.0.2.1".parse().unwrap();
+        let mut debounce = Debounce::new(1, Duration::from_secs(300), None);
+        assert!(debounce.observe(ip, now).is_err());
+        assert!(debounce
+            .observe(ip, now + Duration::from_secs(299))
+            .is_err());
+        assert_eq!(debounce.observe(ip, now + Duration::from_secs(300)), Ok(()));
+    }
+
+    #[test]
+    fn it_limits_updates_per_hour() {
+        let now = Instant::now();
+        let (a, b) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        let mut debounce = Debounce::new(1, Duration::from_secs(0), Some(1));
+        assert_eq!(debounce.observe(a, now), Ok(()));
+        debounce.published(now);
+        assert_eq!(
+            debounce.observe(b, now + Duration::from_secs(60)),
+            Err(Suppressed::RateLimited { limit: 1 })
+        );
+        assert_eq!(debounce.observe(b, now + Duration::from_secs(3600)), Ok(()));
+    }
+}
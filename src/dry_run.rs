@@ -0,0 +1,135 @@
+use std::fmt;
+
+use duck_dns::{Label, Updated};
+use public_ip::Family;
+use serde::Serialize;
+
+/// A request --dry-run didn't send to DuckDNS, and what it's expected to do,
+/// or the update it would skip
+#[derive(Debug, Serialize)]
+pub struct DryRun {
+    /// always true, setting this apart from a response
+    dry_run: bool,
+    /// the URL, with the token masked, `None` when the update is skipped
+    request: Option<String>,
+    /// the family the request would be sent over, with update --family
+    family: Option<String>,
+    /// whether the domains would change, if that's known from what was last
+    /// published
+    expected: Option<Updated>,
+    /// why the update would be skipped
+    skipped: Option<Skipped>,
+}
+
+#[derive(Debug, Serialize)]
+struct Skipped {
+    domains: String,
+    reason: String,
+}
+
+impl DryRun {
+    pub fn new(request: String, family: Option<Family>, expected: Option<Updated>) -> Self {
+        Self {
+            dry_run: true,
+            request: Some(request),
+            family: family.map(|family| family.to_string()),
+            expected,
+            skipped: None,
+        }
+    }
+
+    pub fn skipped(domains: &[Label], reason: String) -> Self {
+        let domains = domains
+            .iter()
+            .map(|label| label.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        Self {
+            dry_run: true,
+            request: None,
+            family: None,
+            expected: None,
+            skipped: Some(Skipped { domains, reason }),
+        }
+    }
+}
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let request = match (&self.request, &self.skipped) {
+            (Some(request), _) => request,
+            (None, Some(skipped)) => {
+                return write!(
+                    f,
+                    "dry run, would skip updating {}: {}",
+                    skipped.domains, skipped.reason
+                )
+            }
+            (None, None) => unreachable!("a dry run has a request or is skipped"),
+        };
+        write!(f, "dry run, would request {}", request)?;
+        if let Some(ref family) = self.family {
+            write!(f, " over {}", family)?;
+        }
+        match self.expected {
+            Some(updated) => write!(f, ", expecting {}", updated),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use duck_dns::Updated;
+    use public_ip::Family;
+
+    use super::DryRun;
+
+    #[test]
+    fn it_marks_itself_as_a_dry_run() {
+        let request = "https://www.duckdns.org/update?domains=home&token=****";
+        let dry_run = DryRun::new(
+            request.to_owned(),
+            Some(Family::V6),
+            Some(Updated::Nochange),
+        );
+        assert_eq!(
+            serde_json::to_value(&dry_run).unwrap(),
+            serde_json::json!({
+                "dry_run": true,
+                "request": request,
+                "family": "ipv6",
+                "expected": "NOCHANGE",
+                "skipped": null,
+            })
+        );
+        assert_eq!(
+            dry_run.to_string(),
+            format!(
+                "dry run, would request {} over ipv6, expecting NOCHANGE",
+                request
+            )
+        );
+    }
+
+    #[test]
+    fn it_says_why_it_would_skip() {
+        let domains = ["home".parse().unwrap(), "work".parse().unwrap()];
+        let reason = "192.0.2.7 unchanged since last update";
+        let dry_run = DryRun::skipped(&domains, reason.to_owned());
+        assert_eq!(
+            serde_json::to_value(&dry_run).unwrap(),
+            serde_json::json!({
+                "dry_run": true,
+                "request": null,
+                "family": null,
+                "expected": null,
+                "skipped": {"domains": "home,work", "reason": reason},
+            })
+        );
+        assert_eq!(
+            dry_run.to_string(),
+            format!("dry run, would skip updating home,work: {}", reason)
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::{io, net::IpAddr, process::Stdio, time::Duration};
+
+use duck_dns::{Label, Response};
+use log::{debug, info, warn};
+use structopt::StructOpt;
+use tokio::time::timeout;
+
+use crate::{
+    parse_duration::parse_duration,
+    webhook::{Webhook, WebhookError, WebhookOpts},
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(StructOpt, Clone, Debug, Default)]
+pub struct HookOpts {
+    /// Command to run with sh (cmd on Windows) when the published address changes, given
+    /// QUACK_DOMAINS, QUACK_OLD_IPV4, QUACK_NEW_IPV4, QUACK_OLD_IPV6,
+    /// QUACK_NEW_IPV6, QUACK_IPV4, QUACK_IPV6, and QUACK_STATUS
+    #[structopt(long)]
+    pub on_change: Option<String>,
+    /// Command to run with sh (cmd on Windows) when an update fails or DuckDNS responds KO,
+    /// given QUACK_DOMAINS, QUACK_STATUS, and QUACK_ERROR
+    #[structopt(long)]
+    pub on_error: Option<String>,
+    /// How long to let a hook run before killing it, defaults to 30s
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub hook_timeout: Option<Duration>,
+    #[structopt(flatten)]
+    pub webhook: WebhookOpts,
+}
+
+impl HookOpts {
+    /// These hooks, falling back to `defaults` for any not set
+    pub fn or(self, defaults: &HookOpts) -> Self {
+        Self {
+            on_change: self.on_change.or_else(|| defaults.on_change.clone()),
+            on_error: self.on_error.or_else(|| defaults.on_error.clone()),
+            hook_timeout: self.hook_timeout.or(defaults.hook_timeout),
+            webhook: self.webhook.or(&defaults.webhook),
+        }
+    }
+}
+
+/// Runs the --on-change and --on-error commands, and sends webhook
+/// notifications, for a set of domains
+#[derive(Debug)]
+pub struct Hooks {
+    opts: HookOpts,
+    domains: String,
+    webhook: Option<Webhook>,
+}
+
+impl Hooks {
+    pub fn new(opts: HookOpts, domains: &[Label]) -> Result<Self, WebhookError> {
+        let domains = domains
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        let webhook = Webhook::new(opts.webhook.clone(), &domains)?;
+        Ok(Self {
+            opts,
+            domains,
+            webhook,
+        })
+    }
+
+    /// The address for `new`'s family changed from `old`, which is `None`
+    /// if it wasn't known
+    pub async fn changed(&mut self, old: Option<IpAddr>, new: IpAddr, response: &Response) {
+        if old == Some(new) {
+            return;
+        }
+        if let Some(ref mut webhook) = self.webhook {
+            webhook.changed(old, new);
+        }
+        let command = match self.opts.on_change {
+            Some(ref command) => command,
+            None => return,
+        };
+        let (old_var, new_var) = match new {
+            IpAddr::V4(_) => ("QUACK_OLD_IPV4", "QUACK_NEW_IPV4"),
+            IpAddr::V6(_) => ("QUACK_OLD_IPV6", "QUACK_NEW_IPV6"),
+        };
+        let mut env = vec![
+            (old_var, old.map(|ip| ip.to_string()).unwrap_or_default()),
+            (new_var, new.to_string()),
+            ("QUACK_STATUS", response.status().to_string()),
+        ];
+        // what DuckDNS reports it's now serving, when verbose
+        if let Some(ip) = response.ipv4() {
+            env.push(("QUACK_IPV4", ip.to_string()));
+        }
+        if let Some(ip) = response.ipv6() {
+            env.push(("QUACK_IPV6", ip.to_string()));
+        }
+        self.run("on-change", command, env).await;
+    }
+
+    /// An update failed with `error`, with DuckDNS's response if there was
+    /// one
+    pub async fn failed(&mut self, response: Option<&Response>, error: &str) {
+        if let Some(ref mut webhook) = self.webhook {
+            match response {
+                Some(response) => webhook.ko(response.status().to_string()),
+                None => webhook.failed(error),
+            }
+        }
+        let command = match self.opts.on_error {
+            Some(ref command) => command,
+            None => return,
+        };
+        let status = response.map_or_else(|| "ERROR".to_owned(), |r| r.status().to_string());
+        let env = vec![("QUACK_STATUS", status), ("QUACK_ERROR", error.to_owned())];
+        self.run("on-error", command, env).await;
+    }
+
+    /// A cycle went fine, so any run of failures is over
+    pub fn succeeded(&mut self) {
+        if let Some(ref mut webhook) = self.webhook {
+            webhook.succeeded();
+        }
+    }
+
+    /// Wait for any webhook notifications still being delivered
+    pub async fn flush(&mut self) {
+        if let Some(ref mut webhook) = self.webhook {
+            webhook.flush().await;
+        }
+    }
+
+    async fn run(&self, name: &str, command: &str, env: Vec<(&str, String)>) {
+        debug!("running {} hook {:?}", name, command);
+        let limit = self.opts.hook_timeout.unwrap_or(DEFAULT_TIMEOUT);
+        match exec(command, &self.domains, env, limit).await {
+            Ok(output) => {
+                for line in String::from_utf8_lossy(&output.stdout).lines() {
+                    info!("{} hook: {}", name, line);
+                }
+                for line in String::from_utf8_lossy(&output.stderr).lines() {
+                    warn!("{} hook: {}", name, line);
+                }
+                if !output.status.success() {
+                    warn!("{} hook exited with {}", name, output.status);
+                }
+            }
+            Err(e) => warn!("{} hook failed: {}", name, e),
+        }
+    }
+}
+
+async fn exec(
+    command: &str,
+    domains: &str,
+    env: Vec<(&str, String)>,
+    limit: Duration,
+) -> Result<std::process::Output, io::Error> {
+    let child = public_ip::shell(command)
+        .env("QUACK_DOMAINS", domains)
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    timeout(limit, child.wait_with_output())
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "timed out"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process, time::Duration};
+
+    use duck_dns::Label;
+
+    use super::{HookOpts, Hooks};
+
+    #[tokio::test]
+    async fn it_runs_hooks_with_env() {
+        let path = env::temp_dir().join(format!("quack-hook-{}.txt", process::id()));
+        let domains: [Label; 2] = ["home".parse().unwrap(), "work".parse().unwrap()];
+        let mut hooks = Hooks::new(
+            HookOpts {
+                on_change: Some(format!(
+                    "echo \"$QUACK_DOMAINS $QUACK_OLD_IPV4 $QUACK_NEW_IPV4 $QUACK_STATUS\" > {}",
+                    path.display()
+                )),
+                on_error: Some(format!(
+                    "echo \"$QUACK_STATUS $QUACK_ERROR\" >> {}",
+                    path.display()
+                )),
+                hook_timeout: None,
+                webhook: Default::default(),
+            },
+            &domains,
+        )
+        .unwrap();
+        let response = "OK".parse().unwrap();
+        let old = "192.0.2.1".parse().unwrap();
+        hooks.changed(Some(old), old, &response).await;
+        assert!(!path.exists());
+        hooks
+            .changed(Some(old), "192.0.2.2".parse().unwrap(), &response)
+            .await;
+        hooks.failed(None, "timed out").await;
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            contents,
+            "home,work 192.0.2.1 192.0.2.2 OK\nERROR timed out\n"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_kills_slow_hooks() {
+        let mut hooks = Hooks::new(
+            HookOpts {
+                on_change: None,
+                on_error: Some("sleep 5".to_owned()),
+                hook_timeout: Some(Duration::from_millis(100)),
+                webhook: Default::default(),
+            },
+            &[],
+        )
+        .unwrap();
+        let start = std::time::Instant::now();
+        hooks.failed(None, "failed").await;
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -1,74 +1,146 @@
 mod commands {
     pub mod check_ip;
     pub mod clear;
+    // signals, pidfile locking, and sd_notify are unix only
+    #[cfg(unix)]
+    pub mod daemon;
     pub mod txt;
     pub mod update;
 }
 mod check_ip_opts;
+mod config;
+// parts only the daemon supervises with, or serves status pages from
+#[cfg_attr(not(unix), allow(dead_code))]
+mod control;
+mod debounce;
+mod dry_run;
+mod hooks;
+#[cfg_attr(not(unix), allow(dead_code))]
+mod metrics;
+#[cfg(unix)]
+mod notify;
 mod opts;
+mod output;
 mod parse_duration;
+#[cfg(unix)]
+mod pidfile;
+mod policy;
+mod reconcile;
+mod redact;
+mod server;
+mod state;
+mod webhook;
 
 use std::error::Error as StdError;
 
-use log::{debug, error};
+use log::{debug, error, LevelFilter};
 use structopt::StructOpt;
 use tokio::runtime::Runtime;
 
-use crate::opts::{Command, Opts};
+use crate::{
+    opts::{Command, Opts},
+    output::{error_code, responses_code, Output},
+    redact::Redact,
+};
 
 fn main() {
-    if let Err(e) = run() {
-        error!("{}", e);
-        drop(e);
-        std::process::exit(1)
+    match run() {
+        Ok(0) => (),
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            error!("{}", e);
+            let code = error_code(&*e);
+            drop(e);
+            std::process::exit(code)
+        }
     }
 }
 
-fn run() -> Result<(), Box<dyn StdError>> {
-    let opts = Opts::from_args().propagate_verbose();
+fn run() -> Result<i32, Box<dyn StdError>> {
+    let opts = Opts::from_args().propagate_verbose().propagate_config();
 
     let mut logger = stderrlog::new();
     // most of the time we want to scope logging to just this codebase, but
     // extra verbose -vvvvv level turns on logging for all modules, but only
-    // in debug builds (it might leak tokens, so isn't safe for release builds)
+    // in debug builds (it might leak tokens, so isn't safe for release builds).
+    // Tokens are masked in everything logged too, but other crates could log
+    // them in a form that isn't recognised
     if opts.verbose < 5 || !cfg!(debug_assertions) {
         logger.modules(vec![module_path!(), "duck_dns", "public_ip"]);
     }
-    logger.quiet(opts.quiet)
-        .verbosity(opts.verbose)
+    logger
+        .quiet(opts.quiet)
+        .verbosity(opts.verbose + 1)
         .timestamp(stderrlog::Timestamp::Off)
         .color(stderrlog::ColorChoice::Never)
         .show_level(false)
-        .show_module_names(false)
-        .init()?;
+        .show_module_names(false);
+    // warnings are shown by default, so what's been skipped or failed in
+    // the background isn't silent
+    let level = match opts.verbose {
+        _ if opts.quiet => LevelFilter::Off,
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    Redact::new(logger).init(level)?;
 
     debug!("{:#?}", opts);
+    let output = Output::new(opts.output, opts.template);
+    // responses may be verbose for --nochange-exit-code, but are only shown
+    // without --output when asked for
+    let shown = opts.verbose > 0;
+    let nochange = opts.nochange_exit_code;
+    let command = opts.command;
 
     Runtime::new()?.block_on(async {
-        match opts.command {
+        let code = match command {
+            // what a dry run would send is always shown, as that's its point
+            Command::Update(c) if c.dry_run => {
+                for dry_run in c.plan().await? {
+                    output.print(&dry_run, true)?;
+                }
+                0
+            }
             Command::Update(c) => {
-                let verbose = c.verbose;
-                let output = c.run().await?;
-                if verbose {
-                    println!("{}", output);
-                };
+                let responses = c.run().await?;
+                for response in &responses {
+                    output.print(response, shown)?;
+                }
+                responses_code(
+                    responses.iter().map(|r| (r.status(), r.updated())),
+                    nochange,
+                )
+            }
+            Command::Txt(c) if c.dry_run => {
+                output.print(&c.plan(), true)?;
+                0
             }
             Command::Txt(c) => {
-                let verbose = c.verbose;
-                let output = c.run().await?;
-                if verbose {
-                    println!("{}", output);
-                };
+                let response = c.run().await?;
+                output.print(&response, shown)?;
+                responses_code(Some((response.status(), response.updated())), nochange)
+            }
+            Command::Clear(c) if c.dry_run => {
+                output.print(&c.plan(), true)?;
+                0
             }
             Command::Clear(c) => {
-                let verbose = c.verbose;
-                let output = c.run().await?;
-                if verbose {
-                    println!("{}", output);
-                };
+                let response = c.run().await?;
+                output.print(&response, shown)?;
+                responses_code(Some((response.status(), response.updated())), nochange)
+            }
+            Command::CheckIp(c) => {
+                output.print(&c.run().await?, true)?;
+                0
+            }
+            #[cfg(unix)]
+            Command::Daemon(c) => {
+                c.run().await?;
+                0
             }
-            Command::CheckIp(c) => println!("{}", c.run().await?),
         };
-        Ok(())
+        Ok(code)
     })
 }
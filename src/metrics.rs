@@ -0,0 +1,603 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use duck_dns::{Label, Response};
+use log::warn;
+use serde_json::{json, Value};
+use structopt::StructOpt;
+
+use crate::{
+    control::Cycle,
+    server::{self, Page},
+    state::State,
+};
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+#[derive(StructOpt, Clone, Debug, Default)]
+pub struct MetricsOpts {
+    /// Serve Prometheus metrics from http://<address>/metrics, with --schedule
+    #[structopt(long)]
+    pub metrics_listen: Option<SocketAddr>,
+    /// Write Prometheus metrics to this file after each update, for a
+    /// textfile collector, with --schedule
+    #[structopt(long, parse(from_os_str))]
+    pub metrics_file: Option<PathBuf>,
+}
+
+impl MetricsOpts {
+    pub fn is_enabled(&self) -> bool {
+        self.metrics_listen.is_some() || self.metrics_file.is_some()
+    }
+}
+
+/// A metric name suffix and labels, and the value
+type Sample = (String, String);
+
+#[derive(Debug, Default)]
+struct Check {
+    count: u64,
+    seconds: f64,
+    failures: u64,
+    last_checked: Option<SystemTime>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug)]
+struct LastResponse {
+    at: SystemTime,
+    status: String,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    updated: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    domains: Vec<Label>,
+    since: Option<SystemTime>,
+    attempts: u64,
+    errors: u64,
+    /// by OK/KO
+    responses: BTreeMap<String, u64>,
+    /// by UPDATED/NOCHANGE, only reported in verbose responses
+    changes: BTreeMap<String, u64>,
+    /// by IP source spec
+    checks: BTreeMap<String, Check>,
+    retries: u64,
+    detected: Option<IpAddr>,
+    last_success: Option<SystemTime>,
+    last_update: Option<SystemTime>,
+    last_response: Option<LastResponse>,
+    last_error: Option<String>,
+    next_run: Option<SystemTime>,
+}
+
+impl Group {
+    /// Gone longer than `limit` without a successful cycle
+    fn is_stale(&self, limit: Duration) -> bool {
+        self.last_success
+            .or(self.since)
+            .and_then(|t| t.elapsed().ok())
+            .is_some_and(|elapsed| elapsed > limit)
+    }
+
+    fn status(&self, limit: Duration) -> Value {
+        let sources = self
+            .checks
+            .iter()
+            .map(|(source, check)| {
+                let status = json!({
+                    "healthy": check.last_checked.is_some() && check.last_error.is_none(),
+                    "last_checked": check.last_checked.map(unix_time),
+                    "last_error": check.last_error,
+                    "checks": check.count,
+                    "failures": check.failures,
+                });
+                (source.clone(), status)
+            })
+            .collect::<serde_json::Map<_, _>>();
+        json!({
+            "domains": self.domains.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "healthy": !self.is_stale(limit),
+            "detected_ip": self.detected,
+            "last_success": self.last_success.map(unix_time),
+            "last_update": self.last_update.map(unix_time),
+            "last_error": self.last_error,
+            "last_response": self.last_response.as_ref().map(|r| json!({
+                "at": unix_time(r.at),
+                "status": r.status,
+                "ipv4": r.ipv4,
+                "ipv6": r.ipv6,
+                "updated": r.updated,
+            })),
+            "next_run": self.next_run.map(unix_time),
+            "sources": sources,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    groups: BTreeMap<String, Group>,
+    /// what each domain was last updated to, as in the state file
+    published: State,
+}
+
+/// What scheduled updates have been up to, rendered as Prometheus metrics or
+/// a JSON status.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+    file: Option<PathBuf>,
+}
+
+impl Metrics {
+    pub fn new(opts: &MetricsOpts) -> Self {
+        Self {
+            registry: Default::default(),
+            file: opts.metrics_file.clone(),
+        }
+    }
+
+    /// Metrics for `opts`, served from `/metrics` if there's an address to
+    /// listen on
+    pub async fn start(opts: &MetricsOpts) -> Result<Self, io::Error> {
+        let metrics = Self::new(opts);
+        if let Some(addr) = opts.metrics_listen {
+            let listener = server::bind(addr).await?;
+            let served = metrics.clone();
+            tokio::spawn(server::serve(listener, move |path| {
+                (path == "/metrics").then(|| Page::ok(CONTENT_TYPE, served.render()))
+            }));
+        }
+        Ok(metrics)
+    }
+
+    pub fn group(&self, name: &str, domains: &[Label]) -> GroupMetrics {
+        let mut registry = self.registry.lock().unwrap();
+        let group = registry.groups.entry(name.to_owned()).or_default();
+        group.domains = domains.to_vec();
+        group.since.get_or_insert_with(SystemTime::now);
+        GroupMetrics {
+            inner: Some((self.clone(), name.to_owned())),
+        }
+    }
+
+    /// Forget groups that no longer exist, e.g. after a config reload
+    pub fn retain(&self, names: &[&str]) {
+        self.registry
+            .lock()
+            .unwrap()
+            .groups
+            .retain(|name, _| names.contains(&name.as_str()));
+    }
+
+    /// Groups that have gone longer than `limit` without a successful cycle
+    pub fn stale(&self, limit: Duration) -> Vec<String> {
+        self.registry
+            .lock()
+            .unwrap()
+            .groups
+            .iter()
+            .filter(|(_, group)| group.is_stale(limit))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Each group's progress and sources, and what its domains were last
+    /// updated to, as JSON
+    pub fn status(&self, limit: Duration) -> String {
+        let registry = self.registry.lock().unwrap();
+        let groups = registry
+            .groups
+            .iter()
+            .map(|(name, group)| (name.clone(), group.status(limit)))
+            .collect::<serde_json::Map<_, _>>();
+        let domains = registry
+            .groups
+            .values()
+            .flat_map(|group| &group.domains)
+            .filter_map(|domain| {
+                let state = registry.published.domain(domain)?;
+                Some((domain.to_string(), json!(state)))
+            })
+            .collect::<serde_json::Map<_, _>>();
+        let status = json!({
+            "healthy": !registry.groups.values().any(|group| group.is_stale(limit)),
+            "groups": groups,
+            "domains": domains,
+        });
+        format!("{:#}\n", status)
+    }
+
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let groups = &registry.groups;
+        let mut out = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, samples: Vec<Sample>| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        let each = |f: &dyn Fn(&str, &Group) -> Vec<Sample>| {
+            groups
+                .iter()
+                .flat_map(|(name, group)| f(name, group))
+                .collect::<Vec<_>>()
+        };
+
+        family(
+            "quack_update_attempts_total",
+            "counter",
+            "Update requests sent to DuckDNS.",
+            each(&|name, g| vec![(labels(&[("group", name)]), g.attempts.to_string())]),
+        );
+        family(
+            "quack_update_errors_total",
+            "counter",
+            "Update requests that got no response from DuckDNS.",
+            each(&|name, g| vec![(labels(&[("group", name)]), g.errors.to_string())]),
+        );
+        family(
+            "quack_update_responses_total",
+            "counter",
+            "Update responses from DuckDNS, by status.",
+            each(&|name, g| {
+                g.responses
+                    .iter()
+                    .map(|(status, n)| {
+                        (
+                            labels(&[("group", name), ("status", status)]),
+                            n.to_string(),
+                        )
+                    })
+                    .collect()
+            }),
+        );
+        family(
+            "quack_update_changes_total",
+            "counter",
+            "Whether updates changed the record, from verbose DuckDNS responses.",
+            each(&|name, g| {
+                g.changes
+                    .iter()
+                    .map(|(status, n)| {
+                        (
+                            labels(&[("group", name), ("status", status)]),
+                            n.to_string(),
+                        )
+                    })
+                    .collect()
+            }),
+        );
+        family(
+            "quack_ip_check_duration_seconds",
+            "summary",
+            "Time taken to look up our IP, by source.",
+            each(&|name, g| {
+                g.checks
+                    .iter()
+                    .flat_map(|(source, check)| {
+                        let l = labels(&[("group", name), ("source", source)]);
+                        vec![
+                            (format!("_sum{}", l), check.seconds.to_string()),
+                            (format!("_count{}", l), check.count.to_string()),
+                        ]
+                    })
+                    .collect()
+            }),
+        );
+        family(
+            "quack_ip_check_failures_total",
+            "counter",
+            "Failed IP lookups, by source.",
+            each(&|name, g| {
+                g.checks
+                    .iter()
+                    .map(|(source, check)| {
+                        (
+                            labels(&[("group", name), ("source", source)]),
+                            check.failures.to_string(),
+                        )
+                    })
+                    .collect()
+            }),
+        );
+        family(
+            "quack_retries_total",
+            "counter",
+            "Cycles retried early after a failure.",
+            each(&|name, g| vec![(labels(&[("group", name)]), g.retries.to_string())]),
+        );
+        family(
+            "quack_detected_ip_info",
+            "gauge",
+            "The IP address last detected.",
+            each(&|name, g| {
+                g.detected
+                    .iter()
+                    .map(|ip| {
+                        (
+                            labels(&[("group", name), ("ip", &ip.to_string())]),
+                            "1".to_owned(),
+                        )
+                    })
+                    .collect()
+            }),
+        );
+        family(
+            "quack_last_success_timestamp_seconds",
+            "gauge",
+            "When the record was last confirmed correct or updated.",
+            each(&|name, g| timestamp(name, g.last_success)),
+        );
+        family(
+            "quack_last_update_timestamp_seconds",
+            "gauge",
+            "When an update was last accepted by DuckDNS.",
+            each(&|name, g| timestamp(name, g.last_update)),
+        );
+        out
+    }
+
+    fn modify<F>(&self, name: &str, f: F)
+    where
+        F: FnOnce(&mut Group),
+    {
+        f(self
+            .registry
+            .lock()
+            .unwrap()
+            .groups
+            .entry(name.to_owned())
+            .or_default());
+    }
+
+    fn write_file(&self) {
+        if let Some(ref path) = self.file {
+            if let Err(e) = write(path, &self.render()) {
+                warn!("couldn't write metrics to {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+// write then rename, so the collector never reads a partial file
+fn write(path: &Path, contents: &str) -> Result<(), io::Error> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// One group's share of the [`Metrics`], or nothing if metrics are disabled
+#[derive(Clone, Debug, Default)]
+pub struct GroupMetrics {
+    inner: Option<(Metrics, String)>,
+}
+
+impl GroupMetrics {
+    fn modify<F>(&self, f: F)
+    where
+        F: FnOnce(&mut Group),
+    {
+        if let Some((ref metrics, ref name)) = self.inner {
+            metrics.modify(name, f);
+        }
+    }
+
+    /// Record an IP lookup from `source`
+    pub fn checked(&self, source: &str, took: Duration, result: Result<IpAddr, String>) {
+        self.modify(|g| {
+            let check = g.checks.entry(source.to_owned()).or_default();
+            check.count += 1;
+            check.seconds += took.as_secs_f64();
+            check.last_checked = Some(SystemTime::now());
+            match result {
+                Ok(ip) => {
+                    g.detected = Some(ip);
+                    check.last_error = None;
+                }
+                Err(e) => {
+                    check.failures += 1;
+                    check.last_error = Some(e);
+                }
+            }
+        });
+    }
+
+    /// Record an update request, with the response if there was one
+    pub fn updated(&self, response: Option<&Response>) {
+        self.modify(|g| {
+            g.attempts += 1;
+            match response {
+                Some(response) => {
+                    g.last_response = Some(LastResponse {
+                        at: SystemTime::now(),
+                        status: response.status().to_string(),
+                        ipv4: response.ipv4().copied(),
+                        ipv6: response.ipv6().copied(),
+                        updated: response.updated().map(|u| u.to_string()),
+                    });
+                    *g.responses
+                        .entry(response.status().to_string())
+                        .or_default() += 1;
+                    if let Some(updated) = response.updated() {
+                        *g.changes.entry(updated.to_string()).or_default() += 1;
+                    }
+                }
+                None => g.errors += 1,
+            }
+        });
+    }
+
+    pub fn retried(&self) {
+        self.modify(|g| g.retries += 1);
+    }
+
+    /// Note when the next cycle is due
+    pub fn sleeping(&self, duration: Duration) {
+        self.modify(|g| g.next_run = Some(SystemTime::now() + duration));
+    }
+
+    /// Copy what the group's domains were last updated to from `state`
+    pub fn restore(&self, state: &State) {
+        self.publish(|published, domains| published.restore(state, domains));
+    }
+
+    /// Record a successful update, as [`State`] does
+    pub fn published(&self, ips: &[IpAddr], response: &Response) {
+        self.publish(|published, domains| {
+            for ip in ips {
+                published.record_ip(domains, *ip);
+            }
+            published.record_response(domains, response);
+        });
+    }
+
+    fn publish<F>(&self, f: F)
+    where
+        F: FnOnce(&mut State, &[Label]),
+    {
+        if let Some((ref metrics, ref name)) = self.inner {
+            let mut registry = metrics.registry.lock().unwrap();
+            let domains = registry
+                .groups
+                .get(name)
+                .map(|g| g.domains.clone())
+                .unwrap_or_default();
+            f(&mut registry.published, &domains);
+        }
+    }
+
+    /// Record the outcome of a cycle, and write the metrics file
+    pub fn finished(&self, cycle: &Cycle) {
+        let now = SystemTime::now();
+        self.modify(|g| match cycle {
+            Cycle::Updated(_) => {
+                g.last_success = Some(now);
+                g.last_update = Some(now);
+            }
+            Cycle::Unchanged(_) => g.last_success = Some(now),
+            Cycle::Failed(e) => g.last_error = Some(e.clone()),
+        });
+        if !cycle.is_failed() {
+            self.modify(|g| g.last_error = None);
+        }
+        if let Some((ref metrics, _)) = self.inner {
+            metrics.write_file();
+        }
+    }
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs = pairs
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{{{}}}", pairs)
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn timestamp(name: &str, time: Option<SystemTime>) -> Vec<Sample> {
+    time.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| (labels(&[("group", name)]), d.as_secs().to_string()))
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use duck_dns::Label;
+    use serde_json::{json, Value};
+
+    use super::{Metrics, MetricsOpts};
+    use crate::control::Cycle;
+
+    #[test]
+    fn it_renders_prometheus_text() {
+        let metrics = Metrics::new(&MetricsOpts::default());
+        let group = metrics.group("home \"v4\"", &[]);
+        group.checked(
+            "opendns",
+            Duration::from_millis(250),
+            Err("timed out".to_owned()),
+        );
+        group.checked(
+            "opendns",
+            Duration::from_millis(250),
+            Ok("192.0.2.1".parse().unwrap()),
+        );
+        group.updated(Some(&"OK\n192.0.2.1\n\nUPDATED".parse().unwrap()));
+        group.updated(None);
+        group.finished(&Cycle::Updated(None));
+        let text = metrics.render();
+        for line in &[
+            "# TYPE quack_update_attempts_total counter",
+            r#"quack_update_attempts_total{group="home \"v4\""} 2"#,
+            r#"quack_update_errors_total{group="home \"v4\""} 1"#,
+            r#"quack_update_responses_total{group="home \"v4\"",status="OK"} 1"#,
+            r#"quack_update_changes_total{group="home \"v4\"",status="UPDATED"} 1"#,
+            r#"quack_ip_check_duration_seconds_sum{group="home \"v4\"",source="opendns"} 0.5"#,
+            r#"quack_ip_check_duration_seconds_count{group="home \"v4\"",source="opendns"} 2"#,
+            r#"quack_ip_check_failures_total{group="home \"v4\"",source="opendns"} 1"#,
+            r#"quack_detected_ip_info{group="home \"v4\"",ip="192.0.2.1"} 1"#,
+        ] {
+            assert!(text.contains(line), "{} not in\n{}", line, text);
+        }
+        assert!(text.contains("quack_last_success_timestamp_seconds{"));
+    }
+
+    #[test]
+    fn it_reports_status_and_staleness() {
+        let metrics = Metrics::new(&MetricsOpts::default());
+        let domains: [Label; 1] = ["home".parse().unwrap()];
+        let group = metrics.group("home", &domains);
+        let ip = "192.0.2.1".parse().unwrap();
+        group.checked("opendns", Duration::from_millis(10), Ok(ip));
+        let response = "OK\n192.0.2.1\n\nUPDATED".parse().unwrap();
+        group.updated(Some(&response));
+        group.published(&[ip], &response);
+        group.finished(&Cycle::Updated(Some(ip)));
+        group.sleeping(Duration::from_secs(300));
+
+        let status: Value = serde_json::from_str(&metrics.status(Duration::from_secs(60))).unwrap();
+        assert_eq!(status["healthy"], true);
+        let home = &status["groups"]["home"];
+        assert_eq!(home["domains"], json!(["home"]));
+        assert_eq!(home["detected_ip"], "192.0.2.1");
+        assert_eq!(home["last_response"]["status"], "OK");
+        assert_eq!(home["last_response"]["updated"], "UPDATED");
+        assert_eq!(home["sources"]["opendns"]["healthy"], true);
+        assert!(home["next_run"].is_u64());
+        assert_eq!(status["domains"]["home"]["ipv4"], "192.0.2.1");
+
+        assert!(metrics.stale(Duration::from_secs(60)).is_empty());
+        metrics.group("never", &[]);
+        assert!(metrics.stale(Duration::from_secs(60)).is_empty());
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(metrics.stale(Duration::from_millis(10)), ["home", "never"]);
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    env, io,
+    os::unix::net::{SocketAddr, UnixDatagram},
+    process,
+    time::Duration,
+};
+
+use log::{debug, warn};
+
+/// Sends service manager notifications, as in systemd's `sd_notify`, to the
+/// socket in `$NOTIFY_SOCKET`. Without one, notifications are dropped.
+#[derive(Debug)]
+pub struct Notifier {
+    socket: Option<(UnixDatagram, SocketAddr)>,
+}
+
+impl Notifier {
+    pub fn from_env() -> Self {
+        match env::var("NOTIFY_SOCKET") {
+            Ok(path) => Self::new(&path).unwrap_or_else(|e| {
+                warn!("can't notify {}: {}", path, e);
+                Self::disabled()
+            }),
+            Err(_) => Self::disabled(),
+        }
+    }
+
+    /// A notifier for the socket at `path`, a leading '@' is an abstract
+    /// socket name
+    pub fn new(path: &str) -> Result<Self, io::Error> {
+        let addr = match path.strip_prefix('@') {
+            Some(name) => abstract_addr(name)?,
+            None => SocketAddr::from_pathname(path)?,
+        };
+        Ok(Self {
+            socket: Some((UnixDatagram::unbound()?, addr)),
+        })
+    }
+
+    pub fn disabled() -> Self {
+        Self { socket: None }
+    }
+
+    /// Send newline separated `KEY=value` assignments
+    pub fn notify(&self, state: &str) {
+        if let Some((ref socket, ref addr)) = self.socket {
+            debug!("notifying {}", state.replace('\n', " "));
+            if let Err(e) = socket.send_to_addr(state.as_bytes(), addr) {
+                warn!("notify failed: {}", e);
+            }
+        }
+    }
+
+    /// How often the service manager expects `WATCHDOG=1`, if at all
+    pub fn watchdog(&self) -> Option<Duration> {
+        self.socket.as_ref()?;
+        // the watchdog may be meant for another process, e.g. a wrapper
+        if let Ok(pid) = env::var("WATCHDOG_PID") {
+            if pid.parse() != Ok(process::id()) {
+                return None;
+            }
+        }
+        let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+        Some(Duration::from_micros(usec)).filter(|d| !d.is_zero())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &str) -> Result<SocketAddr, io::Error> {
+    use std::os::linux::net::SocketAddrExt;
+    SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_addr(_: &str) -> Result<SocketAddr, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "abstract sockets are only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, os::unix::net::UnixDatagram, process};
+
+    use super::Notifier;
+
+    #[test]
+    fn it_sends_to_socket_path() {
+        let path = env::temp_dir().join(format!("quack-notify-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let notifier = Notifier::new(path.to_str().unwrap()).unwrap();
+        notifier.notify("READY=1\nSTATUS=ok");
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1\nSTATUS=ok");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn it_sends_to_abstract_socket() {
+        use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+        let name = format!("quack-notify-{}", process::id());
+        let addr = SocketAddr::from_abstract_name(&name).unwrap();
+        let receiver = UnixDatagram::bind_addr(&addr).unwrap();
+        Notifier::new(&format!("@{}", name))
+            .unwrap()
+            .notify("WATCHDOG=1");
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"WATCHDOG=1");
+    }
+
+    #[test]
+    fn it_drops_notifications_without_socket() {
+        let notifier = Notifier::disabled();
+        notifier.notify("READY=1");
+        assert_eq!(notifier.watchdog(), None);
+    }
+}
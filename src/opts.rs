@@ -1,9 +1,18 @@
+use std::path::PathBuf;
+
 use duck_dns::{Label, Token};
 use structopt::StructOpt;
 
-use crate::commands::{check_ip::CheckIp, clear::Clear, txt::Txt, update::Update};
+#[cfg(unix)]
+use crate::commands::daemon::Daemon;
+use crate::{
+    commands::{check_ip::CheckIp, clear::Clear, txt::Txt, update::Update},
+    output::{OutputFormat, EXIT_CODES},
+    state::StateFile,
+};
 
 #[derive(StructOpt, Debug)]
+#[structopt(after_help = EXIT_CODES)]
 pub struct Opts {
     /// Silence all output
     #[structopt(short = "q", long = "quiet", conflicts_with = "verbose")]
@@ -11,16 +20,33 @@ pub struct Opts {
     /// Verbose mode, multiples increase the verbosity
     #[structopt(short, long, global = true, parse(from_occurrences))]
     pub verbose: usize,
+    /// Config file defining accounts and groups of domains, see `update --all`
+    #[structopt(long, global = true, env = "QUACK_CONFIG", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+    /// Print results as json, plain, or with --template, json and template
+    /// ask DuckDNS for the addresses and whether anything changed
+    #[structopt(long, global = true)]
+    pub output: Option<OutputFormat>,
+    /// Template for --output template, with {field} replaced by that field
+    /// of the json output, e.g. '{status} {ipv4}'
+    #[structopt(long, global = true)]
+    pub template: Option<String>,
+    /// Exit with status 5 when DuckDNS reports NOCHANGE for every domain
+    #[structopt(long, global = true)]
+    pub nochange_exit_code: bool,
     #[structopt(subcommand)]
     pub command: Command,
 }
 
 #[derive(StructOpt, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Command {
     Update(Update),
     Txt(Txt),
     Clear(Clear),
     CheckIp(CheckIp),
+    #[cfg(unix)]
+    Daemon(Daemon),
 }
 
 #[derive(StructOpt, Debug)]
@@ -37,17 +63,100 @@ impl From<Account> for duck_dns::Client {
     }
 }
 
+#[derive(StructOpt, Clone, Debug, Default)]
+pub struct StateOpts {
+    /// File to record what was last published in, defaults to state.json in
+    /// $STATE_DIRECTORY, $XDG_STATE_HOME/quack, ~/.local/state/quack, or /var/lib/quack
+    #[structopt(long, env = "QUACK_STATE_FILE", parse(from_os_str))]
+    pub state_file: Option<PathBuf>,
+    /// Don't read or write the state file
+    #[structopt(long, conflicts_with = "state-file")]
+    pub no_state: bool,
+}
+
+impl From<StateOpts> for StateFile {
+    fn from(value: StateOpts) -> Self {
+        if value.no_state {
+            Self::disabled()
+        } else {
+            Self::new(value.state_file)
+        }
+    }
+}
+
 impl Opts {
-    pub fn propagate_verbose(mut self) -> Self {
-        if self.verbose == 0 {
-            return self;
+    pub fn propagate_config(mut self) -> Self {
+        match self.command {
+            Command::Update(ref mut c) => c.config = self.config.clone(),
+            #[cfg(unix)]
+            Command::Daemon(ref mut c) => c.config = self.config.clone(),
+            _ => (),
         }
+        self
+    }
+
+    pub fn propagate_verbose(mut self) -> Self {
+        let detailed = matches!(
+            self.output,
+            Some(OutputFormat::Json) | Some(OutputFormat::Template)
+        );
+        let verbose = self.verbose > 0 || detailed;
+        // NOCHANGE is only in verbose responses, so they're always requested
+        // for its exit status, whatever the output format
+        let verbose_response = verbose || self.nochange_exit_code;
         match self.command {
-            Command::Update(ref mut c) => c.verbose = true,
-            Command::Txt(ref mut c) => c.verbose = true,
-            Command::Clear(ref mut c) => c.verbose = true,
+            Command::Update(ref mut c) => c.verbose = verbose_response,
+            Command::Txt(ref mut c) => c.verbose = verbose_response,
+            Command::Clear(ref mut c) => c.verbose = verbose_response,
+            #[cfg(unix)]
+            Command::Daemon(ref mut c) => c.verbose = verbose,
             Command::CheckIp(_) => (),
         };
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use duck_dns::Response;
+    use structopt::StructOpt;
+
+    use super::{Command, Opts};
+    use crate::output::{responses_code, EXIT_NOCHANGE};
+
+    // the exit status for a no-op update, answered the way DuckDNS would for
+    // the arguments given
+    fn nochange_code(args: &[&str]) -> i32 {
+        let opts = Opts::from_iter(args).propagate_verbose();
+        let verbose = match opts.command {
+            Command::Update(ref c) => c.verbose,
+            _ => unreachable!(),
+        };
+        let response: Response = if verbose {
+            "OK\n192.0.2.1\n\nNOCHANGE"
+        } else {
+            "OK"
+        }
+        .parse()
+        .unwrap();
+        responses_code(
+            Some((response.status(), response.updated())),
+            opts.nochange_exit_code,
+        )
+    }
+
+    #[test]
+    fn it_exits_the_same_for_any_output() {
+        let plain = ["quack", "update", "--token", "t", "home"];
+        let json = [
+            "quack", "--output", "json", "update", "--token", "t", "home",
+        ];
+        assert_eq!(nochange_code(&plain), 0);
+        assert_eq!(nochange_code(&json), 0);
+
+        let plain = ["quack", "--nochange-exit-code", "update", "-t", "t", "home"];
+        let json = [&json[..], &["--nochange-exit-code"]].concat();
+        assert_eq!(nochange_code(&plain), EXIT_NOCHANGE);
+        assert_eq!(nochange_code(&json), EXIT_NOCHANGE);
+    }
+}
@@ -0,0 +1,216 @@
+use std::{error::Error as StdError, fmt, fmt::Display, str::FromStr};
+
+use duck_dns::{Status, Updated};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Exit statuses, documented in `quack --help`
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_KO: i32 = 2;
+pub const EXIT_NETWORK: i32 = 3;
+pub const EXIT_PARSE: i32 = 4;
+pub const EXIT_NOCHANGE: i32 = 5;
+
+pub const EXIT_CODES: &str = "EXIT STATUS:
+    0    success
+    1    any other error, including bad arguments
+    2    DuckDNS responded KO
+    3    network error, or a bad HTTP response
+    4    a response couldn't be parsed
+    5    DuckDNS reported NOCHANGE for every domain, with --nochange-exit-code";
+
+#[derive(Debug)]
+pub struct ParseOutputFormatError();
+
+impl fmt::Display for ParseOutputFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "output must be one of json, plain, or template")
+    }
+}
+
+impl StdError for ParseOutputFormatError {}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    Json,
+    Plain,
+    Template,
+}
+
+impl FromStr for OutputFormat {
+    type Err = ParseOutputFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "plain" => Ok(OutputFormat::Plain),
+            "template" => Ok(OutputFormat::Template),
+            _ => Err(ParseOutputFormatError()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MissingTemplateError();
+
+impl fmt::Display for MissingTemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "--output template needs a --template")
+    }
+}
+
+impl StdError for MissingTemplateError {}
+
+/// Prints command results as chosen with --output
+#[derive(Debug)]
+pub struct Output {
+    format: Option<OutputFormat>,
+    template: Option<String>,
+}
+
+impl Output {
+    /// Without a format results are printed as plain text, if `shown`
+    pub fn new(format: Option<OutputFormat>, template: Option<String>) -> Self {
+        Self { format, template }
+    }
+
+    pub fn print<T>(&self, value: &T, shown: bool) -> Result<(), Box<dyn StdError>>
+    where
+        T: Display + Serialize,
+    {
+        match self.format {
+            None if !shown => (),
+            None | Some(OutputFormat::Plain) => println!("{}", value),
+            Some(OutputFormat::Json) => println!("{}", serde_json::to_string(value)?),
+            Some(OutputFormat::Template) => {
+                let template = self.template.as_deref().ok_or(MissingTemplateError())?;
+                println!("{}", render(template, &serde_json::to_value(value)?));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replace `{field}` with the value of each of `value`'s fields
+fn render(template: &str, value: &Value) -> String {
+    let mut out = template.to_owned();
+    if let Value::Object(fields) = value {
+        for (name, field) in fields {
+            let field = match field {
+                Value::Null => String::new(),
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            out = out.replace(&format!("{{{}}}", name), &field);
+        }
+    }
+    out
+}
+
+/// The exit status for a command's responses, NOCHANGE is only reported when
+/// `nochange` is set
+pub fn responses_code<I>(responses: I, nochange: bool) -> i32
+where
+    I: IntoIterator<Item = (Status, Option<Updated>)>,
+{
+    let mut any = false;
+    let mut all_nochange = true;
+    for (status, updated) in responses {
+        if status == Status::Ko {
+            return EXIT_KO;
+        }
+        any = true;
+        all_nochange &= updated == Some(Updated::Nochange);
+    }
+    if nochange && any && all_nochange {
+        EXIT_NOCHANGE
+    } else {
+        0
+    }
+}
+
+/// The exit status for an error
+pub fn error_code(e: &(dyn StdError + 'static)) -> i32 {
+    if let Some(e) = e.downcast_ref::<duck_dns::Error>() {
+        return match e {
+            duck_dns::Error::Http(_) | duck_dns::Error::HttpBadResponse(_) => EXIT_NETWORK,
+            duck_dns::Error::ParseResponse(_) | duck_dns::Error::ParseTxtResponse(_) => EXIT_PARSE,
+        };
+    }
+    if let Some(e) = e.downcast_ref::<public_ip::Error>() {
+        return match e {
+            public_ip::Error::Http(_)
+            | public_ip::Error::HttpBadResponse(_)
+            | public_ip::Error::DnsProto(_)
+            | public_ip::Error::Io(_) => EXIT_NETWORK,
+            public_ip::Error::MissingResponse
+            | public_ip::Error::MissingJsonPath(_)
+            | public_ip::Error::BadJsonValue(_, _)
+            | public_ip::Error::ParseAddr(_) => EXIT_PARSE,
+            _ => EXIT_ERROR,
+        };
+    }
+    EXIT_ERROR
+}
+
+#[cfg(test)]
+mod tests {
+    use duck_dns::{Response, Status, Updated};
+
+    use super::{
+        error_code, render, responses_code, EXIT_ERROR, EXIT_KO, EXIT_NOCHANGE, EXIT_PARSE,
+    };
+
+    #[test]
+    fn it_renders_templates_from_json() {
+        let response: Response = "OK\n192.0.2.1\n\nUPDATED".parse().unwrap();
+        let value = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "status": "OK",
+                "ipv4": "192.0.2.1",
+                "ipv6": null,
+                "updated": "UPDATED",
+            })
+        );
+        assert_eq!(
+            render("{status} {ipv4} [{ipv6}] {updated} {unknown}", &value),
+            "OK 192.0.2.1 [] UPDATED {unknown}"
+        );
+    }
+
+    #[test]
+    fn it_picks_exit_codes() {
+        assert_eq!(responses_code(vec![], true), 0);
+        assert_eq!(
+            responses_code(vec![(Status::Ok, Some(Updated::Nochange))], true),
+            EXIT_NOCHANGE
+        );
+        assert_eq!(
+            responses_code(vec![(Status::Ok, Some(Updated::Nochange))], false),
+            0
+        );
+        assert_eq!(
+            responses_code(
+                vec![
+                    (Status::Ok, Some(Updated::Nochange)),
+                    (Status::Ok, Some(Updated::Updated)),
+                ],
+                true
+            ),
+            0
+        );
+        assert_eq!(
+            responses_code(vec![(Status::Ok, None), (Status::Ko, None)], true),
+            EXIT_KO
+        );
+
+        let parse = duck_dns::Error::from("nope".parse::<Response>().unwrap_err());
+        assert_eq!(error_code(&parse), EXIT_PARSE);
+        let missing = public_ip::Error::MissingResponse;
+        assert_eq!(error_code(&missing), EXIT_PARSE);
+        let io = std::io::Error::other("boom");
+        assert_eq!(error_code(&io), EXIT_ERROR);
+    }
+}
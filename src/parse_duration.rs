@@ -67,7 +67,7 @@ enum Spec {
     Weeks(u64),
 }
 
-fn parse_spec(s: &str) -> Result<Spec, ParseError> {
+fn parse_spec(s: &str) -> Result<Spec, ParseError<'_>> {
     let result: nom::IResult<&str, _> = all_consuming(pair(
         alt((
             recognize(pair(one_of("123456789"), many0(one_of("0123456789")))),
@@ -110,7 +110,7 @@ impl TryFrom<Spec> for Duration {
     }
 }
 
-pub fn parse_duration(s: &str) -> Result<Duration, ParseError> {
+pub fn parse_duration(s: &str) -> Result<Duration, ParseError<'_>> {
     Ok(Duration::try_from(parse_spec(s)?)?)
 }
 
@@ -0,0 +1,115 @@
+use std::{
+    error::Error as StdError,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+};
+
+use log::debug;
+
+#[derive(Debug)]
+pub enum PidfileError {
+    Io(PathBuf, io::Error),
+    Locked(PathBuf, Option<u32>),
+}
+
+impl fmt::Display for PidfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PidfileError::Io(path, e) => write!(f, "couldn't lock {}: {}", path.display(), e),
+            PidfileError::Locked(path, Some(pid)) => {
+                write!(f, "another instance (pid {}) holds {}", pid, path.display())
+            }
+            PidfileError::Locked(path, None) => {
+                write!(f, "another instance holds {}", path.display())
+            }
+        }
+    }
+}
+
+impl StdError for PidfileError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            PidfileError::Io(_, e) => Some(e),
+            PidfileError::Locked(_, _) => None,
+        }
+    }
+}
+
+/// An exclusively locked file holding our process ID, so only one instance
+/// runs at a time. The lock is released when this is dropped, or if the
+/// process dies.
+#[derive(Debug)]
+pub struct Pidfile {
+    path: PathBuf,
+    // held open to hold the lock
+    _file: File,
+}
+
+impl Pidfile {
+    pub fn lock(path: &Path) -> Result<Self, PidfileError> {
+        let io_err = |e| PidfileError::Io(path.to_owned(), e);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(io_err)?;
+        // flock rather than fcntl locks, as those are dropped when any file
+        // descriptor for the file is closed
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::WouldBlock {
+                return Err(io_err(e));
+            }
+            let mut contents = String::new();
+            let pid = file
+                .read_to_string(&mut contents)
+                .ok()
+                .and_then(|_| contents.trim().parse().ok());
+            return Err(PidfileError::Locked(path.to_owned(), pid));
+        }
+        file.set_len(0).map_err(io_err)?;
+        writeln!(file, "{}", process::id()).map_err(io_err)?;
+        debug!("locked {}", path.display());
+        Ok(Self {
+            path: path.to_owned(),
+            _file: file,
+        })
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        // remove while still holding the lock, so a new instance can't lock
+        // the file only to have it removed from under it
+        let _ = fs::remove_file(&self.path);
+        debug!("unlocking {}", self.path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{Pidfile, PidfileError};
+
+    #[test]
+    fn it_refuses_a_second_lock() {
+        let path = env::temp_dir().join(format!("quack-test-{}.pid", process::id()));
+        let pidfile = Pidfile::lock(&path).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.trim(), process::id().to_string());
+        match Pidfile::lock(&path) {
+            Err(PidfileError::Locked(_, Some(pid))) => assert_eq!(pid, process::id()),
+            other => panic!("expected lock to be held, got {:?}", other),
+        }
+        drop(pidfile);
+        assert!(!path.exists());
+        drop(Pidfile::lock(&path).unwrap());
+    }
+}
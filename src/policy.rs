@@ -0,0 +1,169 @@
+use std::{error::Error as StdError, fmt, net::IpAddr, str::FromStr};
+
+#[derive(Debug)]
+pub struct ParseCidrError();
+
+impl fmt::Display for ParseCidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CIDR must be in the format 'address/prefix-length'")
+    }
+}
+
+impl StdError for ParseCidrError {}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    fn is_v4(&self) -> bool {
+        self.addr.is_ipv4()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ParseCidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| ParseCidrError())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| ParseCidrError())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(ParseCidrError());
+        }
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+#[derive(Debug)]
+pub enum PolicyError {
+    Denied(IpAddr, Cidr),
+    NotAllowed(IpAddr),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::Denied(ip, cidr) => write!(f, "{} is in denied range {}", ip, cidr),
+            PolicyError::NotAllowed(ip) => write!(f, "{} is not in any allowed range", ip),
+        }
+    }
+}
+
+impl StdError for PolicyError {}
+
+/// Which detected addresses may be published.
+///
+/// Deny rules win over allow rules. Allow rules only restrict addresses of
+/// their own family, so allowing an IPv4 range doesn't block all of IPv6.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl Policy {
+    pub fn new(allow: Vec<Cidr>, deny: Vec<Cidr>) -> Self {
+        Self { allow, deny }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn check(&self, ip: &IpAddr) -> Result<(), PolicyError> {
+        if let Some(cidr) = self.deny.iter().find(|cidr| cidr.contains(ip)) {
+            return Err(PolicyError::Denied(*ip, *cidr));
+        }
+        let mut allow = self
+            .allow
+            .iter()
+            .filter(|cidr| cidr.is_v4() == ip.is_ipv4())
+            .peekable();
+        if allow.peek().is_some() && !allow.any(|cidr| cidr.contains(ip)) {
+            return Err(PolicyError::NotAllowed(*ip));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cidr, Policy};
+
+    #[test]
+    fn it_parses_cidr() {
+        assert_eq!(
+            "192.0.2.0/24".parse::<Cidr>().unwrap().to_string(),
+            "192.0.2.0/24"
+        );
+        assert_eq!(
+            "2001:db8::1".parse::<Cidr>().unwrap().to_string(),
+            "2001:db8::1/128"
+        );
+        assert!("192.0.2.0/33".parse::<Cidr>().is_err());
+        assert!("example.com/24".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn it_matches_cidr() {
+        let cidr = "10.0.0.0/8".parse::<Cidr>().unwrap();
+        assert!(cidr.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(!cidr.contains(&"11.0.0.1".parse().unwrap()));
+        assert!(!cidr.contains(&"::ffff:10.0.0.1".parse().unwrap()));
+        assert!("0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(&"192.0.2.1".parse().unwrap()));
+        assert!("2001:db8::/32"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(&"2001:db8:1::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn it_applies_policy() {
+        let policy = Policy::new(
+            vec!["198.51.100.0/24".parse().unwrap()],
+            vec!["198.51.100.128/25".parse().unwrap()],
+        );
+        assert!(policy.check(&"198.51.100.1".parse().unwrap()).is_ok());
+        assert!(policy.check(&"198.51.100.200".parse().unwrap()).is_err());
+        assert!(policy.check(&"203.0.113.1".parse().unwrap()).is_err());
+        // no IPv6 allow rules, so IPv6 isn't restricted
+        assert!(policy.check(&"2001:db8::1".parse().unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,72 @@
+use std::{error::Error as StdError, net::IpAddr};
+
+use duck_dns::Label;
+use public_ip::{DnsRecordType, DnsServer, Family, Name, Service};
+
+const ZONE: &str = "duckdns.org.";
+const NAMESERVER: &str = "ns1.duckdns.org";
+
+/// Checks the addresses DuckDNS is actually serving for our domains, so a
+/// change made elsewhere, e.g. the web UI or another machine, is corrected.
+#[derive(Debug)]
+pub struct Reconcile {
+    server: DnsServer,
+    names: Vec<Name>,
+}
+
+impl Reconcile {
+    /// Checks with DuckDNS's first nameserver if no server is given
+    pub fn new(server: Option<DnsServer>, domains: &[Label]) -> Result<Self, Box<dyn StdError>> {
+        let server = match server {
+            Some(server) => server,
+            None => NAMESERVER.parse()?,
+        };
+        let zone = Name::from_ascii(ZONE)?;
+        let names = domains
+            .iter()
+            .map(|domain| {
+                Name::from_ascii(domain.to_string()).map(|name| name.append_domain(&zone))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { server, names })
+    }
+
+    /// The address being served, or `None` if it's missing for, or differs
+    /// between, any of the domains
+    pub async fn published(&self, family: Family) -> Result<Option<IpAddr>, public_ip::Error> {
+        let record_type = match family {
+            Family::V4 => DnsRecordType::A,
+            Family::V6 => DnsRecordType::AAAA,
+        };
+        let mut published = None;
+        for name in &self.names {
+            let service = Service::dns(self.server.clone(), record_type, name.clone());
+            match service.ip_any_scope(Some(family)).await {
+                Ok(ip) if published.unwrap_or(ip) == ip => published = Some(ip),
+                Ok(_) | Err(public_ip::Error::MissingResponse) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(published)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use duck_dns::Label;
+    use public_ip::Name;
+
+    use super::Reconcile;
+
+    #[test]
+    fn it_queries_duckdns_names() {
+        let domains: Vec<Label> = vec!["home".parse().unwrap(), "bücher".parse().unwrap()];
+        let reconcile = Reconcile::new(None, &domains).unwrap();
+        let names = reconcile
+            .names
+            .iter()
+            .map(Name::to_ascii)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["home.duckdns.org.", "xn--bcher-kva.duckdns.org."]);
+    }
+}
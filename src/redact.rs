@@ -0,0 +1,104 @@
+use duck_dns::scrub_tokens;
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// A logger masking DuckDNS tokens in every message before passing it on,
+/// including messages from other crates that log request URLs
+pub struct Redact<L> {
+    inner: L,
+}
+
+impl<L> Redact<L>
+where
+    L: Log + 'static,
+{
+    pub fn new(inner: L) -> Self {
+        Self { inner }
+    }
+
+    /// Set as the logger, logging at most `level`
+    pub fn init(self, level: LevelFilter) -> Result<(), SetLoggerError> {
+        log::set_max_level(level);
+        log::set_boxed_logger(Box::new(self))
+    }
+}
+
+impl<L> Log for Redact<L>
+where
+    L: Log,
+{
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = scrub_tokens(&record.args().to_string());
+        self.inner.log(
+            &Record::builder()
+                .metadata(record.metadata().clone())
+                .args(format_args!("{}", message))
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use log::{Level, Log, Metadata, Record};
+
+    use super::Redact;
+
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl Log for Capture {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.level() <= Level::Debug
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            let line = format!("{} {}: {}", record.level(), record.target(), record.args());
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn it_masks_tokens_in_messages() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let logger = Redact::new(Capture(lines.clone()));
+        let token = "0f3a2b4c-5d6e-4f70-8192-a3b4c5d6e7f8";
+        let url = format!(
+            "https://www.duckdns.org/update?domains=home&token={}",
+            token
+        );
+        for level in &[Level::Debug, Level::Trace] {
+            logger.log(
+                &Record::builder()
+                    .level(*level)
+                    .target("reqwest::connect")
+                    .args(format_args!("starting new connection: {}", url))
+                    .build(),
+            );
+        }
+        assert_eq!(
+            *lines.lock().unwrap(),
+            [format!(
+                "DEBUG reqwest::connect: starting new connection: \
+                 https://www.duckdns.org/update?domains=home&token={}",
+                "*".repeat(token.len())
+            )]
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use log::{debug, info, warn};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+/// A response from a [`serve`]d handler
+#[derive(Debug)]
+pub struct Page {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Page {
+    pub fn ok(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    fn not_found() -> Self {
+        Self {
+            status: "404 Not Found",
+            content_type: "text/plain",
+            body: "not found\n".to_owned(),
+        }
+    }
+
+    fn bad_request(status: &'static str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: format!("{}\n", status),
+        }
+    }
+}
+
+/// Bind `addr`, so a port that's in use is reported before serving starts
+pub async fn bind(addr: SocketAddr) -> Result<TcpListener, io::Error> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("listening on http://{}", listener.local_addr()?);
+    Ok(listener)
+}
+
+/// A minimal HTTP/1.1 server, answering GET requests for a path with
+/// `handler`, one request per connection.
+pub async fn serve<F>(listener: TcpListener, handler: F)
+where
+    F: Fn(&str) -> Option<Page> + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("couldn't accept connection: {}", e);
+                continue;
+            }
+        };
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream, |path| handler(path)).await {
+                debug!("error serving {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn respond<F>(mut stream: TcpStream, handler: F) -> Result<(), io::Error>
+where
+    F: Fn(&str) -> Option<Page>,
+{
+    // only the request line is needed, anything after is ignored
+    let mut buf = vec![0; 8192];
+    let mut len = 0;
+    while !buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+        if len == buf.len() {
+            break;
+        }
+        let read = timeout(Duration::from_secs(10), stream.read(&mut buf[len..]))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request timed out"))??;
+        if read == 0 {
+            break;
+        }
+        len += read;
+    }
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let mut parts = request.lines().next().unwrap_or_default().split(' ');
+    let page = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) | (Some("HEAD"), Some(target)) => {
+            let path = target.split('?').next().unwrap_or_default();
+            handler(path).unwrap_or_else(Page::not_found)
+        }
+        (Some(_), Some(_)) => Page::bad_request("405 Method Not Allowed"),
+        _ => Page::bad_request("400 Bad Request"),
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        page.status,
+        page.content_type,
+        page.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    if !request.starts_with("HEAD ") {
+        stream.write_all(page.body.as_bytes()).await?;
+    }
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::{bind, serve, Page};
+
+    async fn get(addr: std::net::SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn it_serves_handled_paths() {
+        let listener = bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, |path| {
+            (path == "/hello").then(|| Page::ok("text/plain", "hi\n".to_owned()))
+        }));
+
+        let response = get(addr, "GET /hello?x=1 HTTP/1.1\r\nHost: a\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("Content-Length: 3\r\n"));
+        assert!(response.ends_with("\r\n\r\nhi\n"));
+
+        let response = get(addr, "GET /nope HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let response = get(addr, "POST /hello HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use duck_dns::{Label, Response, Status};
+use log::{debug, warn};
+use public_ip::Family;
+use serde::{Deserialize, Serialize};
+
+/// What was last published for a domain
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DomainState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4: Option<Ipv4Addr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<Ipv6Addr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txt: Option<String>,
+    /// Seconds since the Unix epoch
+    pub updated: u64,
+}
+
+#[derive(Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct State {
+    #[serde(default)]
+    domains: BTreeMap<String, DomainState>,
+}
+
+impl State {
+    pub fn domain(&self, domain: &Label) -> Option<&DomainState> {
+        self.domains.get(&domain.to_string())
+    }
+
+    /// The address last published, if it's the same for all the domains
+    pub fn ip(&self, domains: &[Label], family: Family) -> Option<IpAddr> {
+        let mut ips = domains.iter().map(|domain| {
+            self.domain(domain).and_then(|state| match family {
+                Family::V4 => state.ipv4.map(IpAddr::V4),
+                Family::V6 => state.ipv6.map(IpAddr::V6),
+            })
+        });
+        let first = ips.next()??;
+        ips.all(|ip| ip == Some(first)).then_some(first)
+    }
+
+    /// When the least recently updated of the domains was updated
+    pub fn updated(&self, domains: &[Label]) -> Option<SystemTime> {
+        domains
+            .iter()
+            .map(|domain| self.domain(domain).map(|state| state.updated))
+            .try_fold(u64::MAX, |oldest, updated| Some(oldest.min(updated?)))
+            .filter(|_| !domains.is_empty())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    pub fn record_ip(&mut self, domains: &[Label], ip: IpAddr) {
+        self.modify(domains, |state| match ip {
+            IpAddr::V4(ip) => state.ipv4 = Some(ip),
+            IpAddr::V6(ip) => state.ipv6 = Some(ip),
+        });
+    }
+
+    /// Record the addresses DuckDNS reports (only in verbose responses)
+    pub fn record_response(&mut self, domains: &[Label], response: &Response) {
+        if response.status() != Status::Ok {
+            return;
+        }
+        if let Some(ip) = response.ipv4() {
+            self.record_ip(domains, IpAddr::V4(*ip));
+        }
+        if let Some(ip) = response.ipv6() {
+            self.record_ip(domains, IpAddr::V6(*ip));
+        }
+    }
+
+    pub fn record_txt(&mut self, domains: &[Label], txt: &str) {
+        self.modify(domains, |state| state.txt = Some(txt.to_owned()));
+    }
+
+    pub fn clear_ip(&mut self, domains: &[Label]) {
+        self.modify(domains, |state| {
+            state.ipv4 = None;
+            state.ipv6 = None;
+        });
+    }
+
+    pub fn clear_txt(&mut self, domains: &[Label]) {
+        self.modify(domains, |state| state.txt = None);
+    }
+
+    /// Copy the domains' state from `other`
+    pub fn restore(&mut self, other: &State, domains: &[Label]) {
+        for domain in domains {
+            if let Some(state) = other.domain(domain) {
+                self.domains.insert(domain.to_string(), state.clone());
+            }
+        }
+    }
+
+    fn modify<F>(&mut self, domains: &[Label], f: F)
+    where
+        F: Fn(&mut DomainState),
+    {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        for domain in domains {
+            let state = self.domains.entry(domain.to_string()).or_default();
+            f(state);
+            state.updated = now;
+        }
+    }
+}
+
+/// Where [`State`] is kept between runs, problems reading or writing it are
+/// logged but never stop an update.
+///
+/// The file is small, so it's read and written synchronously, which also
+/// means updates running concurrently on one thread can't interleave a
+/// read-modify-write and lose each other's changes.
+#[derive(Debug)]
+pub struct StateFile {
+    path: Option<PathBuf>,
+}
+
+impl StateFile {
+    /// A state file at the given path, or the default path if `None`
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path: path.or_else(default_path),
+        }
+    }
+
+    /// A state file that is never read or written
+    pub fn disabled() -> Self {
+        Self { path: None }
+    }
+
+    pub fn load(&self) -> State {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return State::default(),
+        };
+        match read(path) {
+            Ok(state) => state,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => State::default(),
+            Err(e) => {
+                warn!("couldn't read state file {}: {}", path.display(), e);
+                State::default()
+            }
+        }
+    }
+
+    /// Apply a change to the latest state in the file
+    pub fn modify<F>(&self, f: F)
+    where
+        F: FnOnce(&mut State),
+    {
+        if let Some(ref path) = self.path {
+            let mut state = self.load();
+            f(&mut state);
+            if let Err(e) = write(path, &state) {
+                warn!("couldn't write state file {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<State, io::Error> {
+    let contents = fs::read(path)?;
+    debug!("read state from {}", path.display());
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+// write then rename, so a crash mid-write can't leave a truncated file
+fn write(path: &Path, state: &State) -> Result<(), io::Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+    fs::rename(&tmp, path)?;
+    debug!("wrote state to {}", path.display());
+    Ok(())
+}
+
+// systemd's StateDirectory=, then the XDG base directory spec, falling back
+// to /var/lib for system services with no home directory
+fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("STATE_DIRECTORY")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("XDG_STATE_HOME").map(|d| PathBuf::from(d).join("quack")))
+        .or_else(|| env::var_os("HOME").map(|d| PathBuf::from(d).join(".local/state/quack")))
+        .unwrap_or_else(|| PathBuf::from("/var/lib/quack"));
+    Some(dir.join("state.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use duck_dns::Label;
+    use public_ip::Family;
+
+    use super::State;
+
+    #[test]
+    fn it_only_returns_ip_shared_by_all_domains() {
+        let domains: [Label; 2] = ["home".parse().unwrap(), "work".parse().unwrap()];
+        let (home, work) = (&domains[..1], &domains[1..]);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut state = State::default();
+        state.record_ip(home, ip);
+        assert_eq!(state.ip(home, Family::V4), Some(ip));
+        assert_eq!(state.ip(home, Family::V6), None);
+        assert_eq!(state.ip(&domains, Family::V4), None);
+        state.record_ip(work, ip);
+        assert_eq!(state.ip(&domains, Family::V4), Some(ip));
+        state.clear_ip(home);
+        assert_eq!(state.ip(&domains, Family::V4), None);
+    }
+
+    #[test]
+    fn it_round_trips_json() {
+        let mut state = State::default();
+        state.record_ip(&["home".parse().unwrap()], "2001:db8::1".parse().unwrap());
+        state.record_txt(&["home".parse().unwrap()], "hello");
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(serde_json::from_str::<State>(&json).unwrap(), state);
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    collections::VecDeque,
+    error::Error as StdError,
+    fmt,
+    net::IpAddr,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+use reqwest::{header::CONTENT_TYPE, RequestBuilder, StatusCode};
+use serde_json::json;
+use structopt::StructOpt;
+use tokio::task::JoinHandle;
+use url::Url;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+pub struct ParseFormatError();
+
+impl fmt::Display for ParseFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "format must be one of json, slack, discord, or ntfy")
+    }
+}
+
+impl StdError for ParseFormatError {}
+
+#[derive(Debug)]
+pub struct WebhookError(reqwest::Error);
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "couldn't set up webhook: {}", self.0)
+    }
+}
+
+impl StdError for WebhookError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// A preset webhook payload
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Format {
+    Json,
+    Slack,
+    Discord,
+    Ntfy,
+}
+
+impl FromStr for Format {
+    type Err = ParseFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "slack" => Ok(Format::Slack),
+            "discord" => Ok(Format::Discord),
+            "ntfy" => Ok(Format::Ntfy),
+            _ => Err(ParseFormatError()),
+        }
+    }
+}
+
+#[derive(StructOpt, Clone, Debug, Default)]
+pub struct WebhookOpts {
+    /// URL to POST notifications of address changes, KO responses, and
+    /// repeated failures to
+    #[structopt(long)]
+    pub webhook: Option<Url>,
+    /// Webhook payload preset, one of json, slack, discord, or ntfy
+    #[structopt(long)]
+    pub webhook_format: Option<Format>,
+    /// Webhook payload, with {event}, {message}, {domains}, {old_ip},
+    /// {new_ip}, {status}, {error}, and {failures} replaced, values are JSON
+    /// escaped if it starts with '{' or '['
+    #[structopt(long, conflicts_with = "webhook-format")]
+    pub webhook_template: Option<String>,
+    /// Notify once this many updates in a row have failed, defaults to 3
+    #[structopt(long)]
+    pub webhook_failures: Option<u32>,
+    /// Send at most this many notifications an hour, defaults to 10
+    #[structopt(long)]
+    pub webhook_max_per_hour: Option<u32>,
+    /// How many times to retry a notification that couldn't be delivered,
+    /// defaults to 2
+    #[structopt(long)]
+    pub webhook_retries: Option<u32>,
+}
+
+impl WebhookOpts {
+    /// These options, falling back to `defaults` for any not set
+    pub fn or(self, defaults: &WebhookOpts) -> Self {
+        Self {
+            webhook: self.webhook.or_else(|| defaults.webhook.clone()),
+            webhook_format: self.webhook_format.or(defaults.webhook_format),
+            webhook_template: self
+                .webhook_template
+                .or_else(|| defaults.webhook_template.clone()),
+            webhook_failures: self.webhook_failures.or(defaults.webhook_failures),
+            webhook_max_per_hour: self.webhook_max_per_hour.or(defaults.webhook_max_per_hour),
+            webhook_retries: self.webhook_retries.or(defaults.webhook_retries),
+        }
+    }
+}
+
+/// Something worth telling a webhook about
+#[derive(Debug)]
+enum Notification {
+    Changed { old: Option<IpAddr>, new: IpAddr },
+    Ko { status: String },
+    Failing { failures: u32, error: String },
+}
+
+impl Notification {
+    fn event(&self) -> &'static str {
+        match self {
+            Notification::Changed { .. } => "changed",
+            Notification::Ko { .. } => "ko",
+            Notification::Failing { .. } => "failing",
+        }
+    }
+
+    fn message(&self, domains: &str) -> String {
+        match self {
+            Notification::Changed {
+                old: Some(old),
+                new,
+            } => {
+                format!("{} changed from {} to {}", domains, old, new)
+            }
+            Notification::Changed { old: None, new } => format!("{} updated to {}", domains, new),
+            Notification::Ko { status } => {
+                format!("{} update, DuckDNS responded {}", domains, status)
+            }
+            Notification::Failing { failures, error } => format!(
+                "{} update failed {} times in a row, {}",
+                domains, failures, error
+            ),
+        }
+    }
+
+    /// Template placeholders and their values
+    fn values(&self, domains: &str) -> Vec<(&'static str, String)> {
+        let (old, new, status, error, failures) = match self {
+            Notification::Changed { old, new } => (
+                old.map(|ip| ip.to_string()).unwrap_or_default(),
+                new.to_string(),
+                "OK".to_owned(),
+                String::new(),
+                0,
+            ),
+            Notification::Ko { status } => (
+                String::new(),
+                String::new(),
+                status.clone(),
+                String::new(),
+                1,
+            ),
+            Notification::Failing { failures, error } => (
+                String::new(),
+                String::new(),
+                "ERROR".to_owned(),
+                error.clone(),
+                *failures,
+            ),
+        };
+        vec![
+            ("event", self.event().to_owned()),
+            ("message", self.message(domains)),
+            ("domains", domains.to_owned()),
+            ("old_ip", old),
+            ("new_ip", new),
+            ("status", status),
+            ("error", error),
+            ("failures", failures.to_string()),
+        ]
+    }
+}
+
+/// POSTs notifications to a webhook, retrying failed deliveries in the
+/// background, and dropping notifications over the hourly limit.
+#[derive(Debug)]
+pub struct Webhook {
+    url: Url,
+    format: Format,
+    template: Option<String>,
+    failures_needed: u32,
+    max_per_hour: u32,
+    retries: u32,
+    retry_delay: Duration,
+    client: reqwest::Client,
+    domains: String,
+    failures: u32,
+    sent: VecDeque<Instant>,
+    pending: Vec<JoinHandle<()>>,
+}
+
+impl Webhook {
+    /// A webhook for `domains`, if `opts` has a URL
+    pub fn new(opts: WebhookOpts, domains: &str) -> Result<Option<Self>, WebhookError> {
+        let url = match opts.webhook {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(WebhookError)?;
+        Ok(Some(Self {
+            url,
+            format: opts.webhook_format.unwrap_or(Format::Json),
+            template: opts.webhook_template,
+            failures_needed: opts.webhook_failures.unwrap_or(3).max(1),
+            max_per_hour: opts.webhook_max_per_hour.unwrap_or(10),
+            retries: opts.webhook_retries.unwrap_or(2),
+            retry_delay: Duration::from_secs(1),
+            client,
+            domains: domains.to_owned(),
+            failures: 0,
+            sent: VecDeque::new(),
+            pending: Vec::new(),
+        }))
+    }
+
+    pub fn changed(&mut self, old: Option<IpAddr>, new: IpAddr) {
+        self.failures = 0;
+        self.send(Notification::Changed { old, new });
+    }
+
+    pub fn ko(&mut self, status: String) {
+        self.failures += 1;
+        self.send(Notification::Ko { status });
+    }
+
+    /// An update failed, notified once enough have failed in a row
+    pub fn failed(&mut self, error: &str) {
+        self.failures += 1;
+        if self.failures == self.failures_needed {
+            let failures = self.failures;
+            let error = error.to_owned();
+            self.send(Notification::Failing { failures, error });
+        }
+    }
+
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Wait for notifications still being delivered, e.g. before exiting
+    pub async fn flush(&mut self) {
+        for task in self.pending.drain(..) {
+            let _ = task.await;
+        }
+    }
+
+    // delivered by a task of its own, so retries don't hold up updates
+    fn send(&mut self, notification: Notification) {
+        let now = Instant::now();
+        while matches!(self.sent.front(), Some(t) if now.duration_since(*t) >= HOUR) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.max_per_hour as usize {
+            info!(
+                "not sending {} webhook, limit of {} an hour reached",
+                notification.event(),
+                self.max_per_hour
+            );
+            return;
+        }
+        self.sent.push_back(now);
+
+        let (content_type, body) = self.payload(&notification);
+        let mut request = self
+            .client
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, content_type)
+            .body(body);
+        if self.format == Format::Ntfy && self.template.is_none() {
+            request = request.header("Title", format!("quack {}", notification.event()));
+            if !matches!(notification, Notification::Changed { .. }) {
+                request = request.header("Priority", "high");
+            }
+        }
+        self.pending.retain(|task| !task.is_finished());
+        self.pending.push(tokio::spawn(deliver(
+            request,
+            notification.event(),
+            self.retries,
+            self.retry_delay,
+        )));
+    }
+
+    fn payload(&self, notification: &Notification) -> (&'static str, String) {
+        let values = notification.values(&self.domains);
+        if let Some(ref template) = self.template {
+            let json = template.trim_start().starts_with(['{', '['].as_ref());
+            let mut body = template.clone();
+            for (name, value) in values {
+                let value = if json {
+                    let quoted = serde_json::to_string(&value).unwrap_or_default();
+                    quoted[1..quoted.len() - 1].to_owned()
+                } else {
+                    value
+                };
+                body = body.replace(&format!("{{{}}}", name), &value);
+            }
+            let content_type = if json {
+                "application/json"
+            } else {
+                "text/plain"
+            };
+            return (content_type, body);
+        }
+        let message = notification.message(&self.domains);
+        let payload = match self.format {
+            Format::Json => {
+                let mut object = serde_json::Map::new();
+                for (name, value) in values {
+                    let value = match value.parse::<u32>() {
+                        Ok(n) if name == "failures" => n.into(),
+                        _ => value.into(),
+                    };
+                    object.insert(name.to_owned(), value);
+                }
+                json!(object)
+            }
+            Format::Slack => json!({ "text": message }),
+            Format::Discord => json!({ "content": message }),
+            Format::Ntfy => return ("text/plain", message),
+        };
+        ("application/json", payload.to_string())
+    }
+}
+
+async fn deliver(request: RequestBuilder, event: &str, retries: u32, mut delay: Duration) {
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+        debug!("sending {} webhook", event);
+        // the body is a string, which can always be cloned
+        let request = request.try_clone().expect("webhook body can be cloned");
+        match request.send().await {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) if !retryable(response.status()) => {
+                warn!("webhook responded {}, not retrying", response.status());
+                return;
+            }
+            Ok(response) => warn!("webhook responded {}", response.status()),
+            Err(e) => warn!("webhook failed: {}", e.without_url()),
+        }
+    }
+    warn!(
+        "giving up on {} webhook after {} attempts",
+        event,
+        retries + 1
+    );
+}
+
+// server errors and rate limiting may pass, other client errors won't
+fn retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use super::{Format, Webhook, WebhookOpts};
+
+    /// Accepts requests, answering with each of `statuses` in turn then 200,
+    /// and sends each request body on
+    async fn receiver(statuses: Vec<u16>) -> (url::Url, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut statuses = statuses.into_iter();
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                let body = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&request).into_owned();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let len = head
+                            .lines()
+                            .find_map(|l| {
+                                l.to_lowercase()
+                                    .strip_prefix("content-length: ")
+                                    .map(str::to_owned)
+                            })
+                            .and_then(|l| l.trim().parse().ok())
+                            .unwrap_or(0);
+                        if body.len() >= len || read == 0 {
+                            break body.to_owned();
+                        }
+                    }
+                };
+                let status = statuses.next().unwrap_or(200);
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+                tx.send(body).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    fn webhook(url: url::Url, opts: WebhookOpts) -> Webhook {
+        let mut webhook = Webhook::new(
+            WebhookOpts {
+                webhook: Some(url),
+                ..opts
+            },
+            "home",
+        )
+        .unwrap()
+        .unwrap();
+        webhook.retry_delay = Duration::from_millis(10);
+        webhook
+    }
+
+    #[tokio::test]
+    async fn it_retries_and_rate_limits() {
+        let (url, mut bodies) = receiver(vec![500]).await;
+        let mut webhook = webhook(
+            url,
+            WebhookOpts {
+                webhook_format: Some(Format::Slack),
+                webhook_max_per_hour: Some(1),
+                ..Default::default()
+            },
+        );
+        webhook.changed(
+            Some("192.0.2.1".parse().unwrap()),
+            "192.0.2.2".parse().unwrap(),
+        );
+        let expected = r#"{"text":"home changed from 192.0.2.1 to 192.0.2.2"}"#;
+        assert_eq!(bodies.recv().await.unwrap(), expected);
+        // retried after the 500
+        assert_eq!(bodies.recv().await.unwrap(), expected);
+        webhook.ko("KO".to_owned());
+        assert!(bodies.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn it_notifies_repeated_failures_with_template() {
+        let (url, mut bodies) = receiver(vec![]).await;
+        let mut webhook = webhook(
+            url,
+            WebhookOpts {
+                webhook_template: Some(r#"{"alert": "{message}", "n": {failures}}"#.to_owned()),
+                webhook_failures: Some(2),
+                ..Default::default()
+            },
+        );
+        webhook.failed("first");
+        webhook.succeeded();
+        webhook.failed("\"quoted\"");
+        webhook.failed("\"quoted\"");
+        webhook.failed("\"quoted\"");
+        let body = bodies.recv().await.unwrap();
+        assert_eq!(
+            body,
+            r#"{"alert": "home update failed 2 times in a row, \"quoted\"", "n": 2}"#
+        );
+        serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert!(bodies.try_recv().is_err());
+    }
+}